@@ -0,0 +1,142 @@
+use anyhow::Error;
+use std::fmt;
+use std::ops::Range;
+use std::sync::*;
+
+/// An error together with where the driver was when it happened
+///
+/// Frames can build one with `Diagnostic::error` and return it with `RecunsFlow::diag`,
+/// the driver then fills in `stack`, `index` and `eof`
+#[derive(Debug, Clone)]
+pub struct Diagnostic<E = Arc<Error>> {
+    pub error: E,
+    pub severity: Severity,
+    /// A stable code tools can match on instead of the message
+    pub code: Option<&'static str>,
+    /// The inputs the error is about, see `primary_span`
+    pub span: Option<Range<usize>>,
+    /// Other spans worth pointing at, such as where an unclosed bracket was opened
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+    /// Frame names from the root to the frame that produced the error
+    pub stack: Vec<&'static str>,
+    /// Index of the input being checked, counted by the driver from 0
+    pub index: usize,
+    /// Whether it happened while handling the end of input
+    pub eof: bool,
+}
+impl<E> Diagnostic<E> {
+    #[inline]
+    pub fn new(error: E, stack: Vec<&'static str>, index: usize, eof: bool) -> Self {
+        Self {
+            error,
+            severity: Severity::Error,
+            code: None,
+            span: None,
+            labels: vec![],
+            notes: vec![],
+            stack,
+            index,
+            eof,
+        }
+    }
+    /// An error for a frame to return, located by the driver
+    #[inline]
+    pub fn error(error: E) -> Self {
+        Self::new(error, vec![], 0, false)
+    }
+    /// A non-fatal diagnostic for `RecunsFlow::warn`
+    #[inline]
+    pub fn warning(error: E) -> Self {
+        let mut d = Self::error(error);
+        d.severity = Severity::Warning;
+        d
+    }
+
+    #[inline]
+    pub fn code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+    #[inline]
+    pub fn span(mut self, span: Range<usize>) -> Self {
+        self.span = Some(span);
+        self
+    }
+    #[inline]
+    pub fn label(mut self, span: Range<usize>, message: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+    #[inline]
+    pub fn note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// `span`, or the input being checked when it has none
+    #[inline]
+    pub fn primary_span(&self) -> Range<usize> {
+        match &self.span {
+            Some(span) => span.clone(),
+            None => self.index..self.index + 1,
+        }
+    }
+    /// The frame stack joined like `root > check_obj > check_arr`
+    #[inline]
+    pub fn path(&self) -> String {
+        self.stack.join(" > ")
+    }
+}
+impl Diagnostic {
+    #[inline]
+    pub fn downcast_ref<E: fmt::Display + fmt::Debug + Send + Sync + 'static>(&self) -> Option<&E> {
+        self.error.downcast_ref()
+    }
+}
+impl<E: fmt::Display> fmt::Display for Diagnostic<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.eof {
+            write!(f, "{} (at EOF in {})", self.error, self.path())
+        } else {
+            write!(
+                f,
+                "{} (at input {} in {})",
+                self.error,
+                self.index,
+                self.path()
+            )
+        }
+    }
+}
+impl std::error::Error for Diagnostic {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.error.as_ref().as_ref())
+    }
+}
+
+/// A secondary span of a `Diagnostic` with what it points at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub span: Range<usize>,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Error => write!(f, "error"),
+            Self::Warning => write!(f, "warning"),
+            Self::Note => write!(f, "note"),
+        }
+    }
+}
@@ -0,0 +1,516 @@
+use crate::*;
+use anyhow::Error;
+use std::any::{type_name, Any};
+use std::collections::VecDeque;
+use std::fmt;
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+#[cfg(feature = "async")]
+use {
+    futures_core::Stream,
+    std::future::poll_fn,
+    std::pin::Pin,
+    std::task::{Context, Poll},
+};
+
+type OnLoop<'a, I, D, E, F> = Box<dyn 'a + FnMut(&mut State<'a, I, D, E, F>)>;
+
+/// Pull-based driver, set it up with the builder methods then `run` or `iter` it
+pub struct Driver<'a, I, D = (), E = Arc<Error>, F = RecunsFrame<'a, I, D, E>> {
+    parser: Parser<'a, I, D, E, F>,
+    cancel: Option<Box<dyn 'a + FnMut() -> bool>>,
+    on_loop: Option<OnLoop<'a, I, D, E, F>>,
+}
+impl<'a, I: 'a, D, E: From<RecunsError> + 'a> Driver<'a, I, D, E> {
+    #[inline]
+    pub fn new(data: D, root: impl Recuns<E, Data = D, Input = I> + 'a) -> Self {
+        Self::with_frames(data, Box::new(root))
+    }
+}
+impl<'a, I: 'a, D, E: From<RecunsError> + 'a, F: Frames<Input = I, Data = D, Error = E>>
+    Driver<'a, I, D, E, F>
+{
+    /// Start from a root frame of any `Frames` type, stopping at the first error
+    #[inline]
+    pub fn with_frames(data: D, root: F) -> Self {
+        Self {
+            parser: Parser::with_frames(data, root, true),
+            cancel: None,
+            on_loop: None,
+        }
+    }
+
+    /// Stop at the first error, `true` by default
+    #[inline]
+    pub fn stop_when_err(mut self, stop: bool) -> Self {
+        self.parser.state.stop_when_err = stop;
+        self
+    }
+    /// Keep going after errors by unwinding to frames that `sync`, see `State::recover`
+    #[inline]
+    pub fn recover(mut self) -> Self {
+        self.parser.state.recover = true;
+        self
+    }
+    /// Checked before every step, returning `true` stops the run without a result
+    #[inline]
+    pub fn cancel(mut self, cancel: impl 'a + FnMut() -> bool) -> Self {
+        self.cancel = Some(Box::new(cancel));
+        self
+    }
+    /// Called with the state before every step
+    #[inline]
+    pub fn on_loop(mut self, on_loop: impl 'a + FnMut(&mut State<'a, I, D, E, F>)) -> Self {
+        self.on_loop = Some(Box::new(on_loop));
+        self
+    }
+    /// See `Parser::max_depth`
+    #[inline]
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.parser = self.parser.max_depth(depth);
+        self
+    }
+    /// See `Parser::fuel`
+    #[inline]
+    pub fn fuel(mut self, fuel: usize) -> Self {
+        self.parser = self.parser.fuel(fuel);
+        self
+    }
+    /// See `Parser::max_redo`
+    #[inline]
+    pub fn max_redo(mut self, max: Option<usize>) -> Self {
+        self.parser = self.parser.max_redo(max);
+        self
+    }
+    /// See `Parser::pool`
+    #[inline]
+    pub fn pool(mut self) -> Self {
+        self.parser = self.parser.pool();
+        self
+    }
+
+    /// Pull inputs from `next` until the end, `Ok(None)` if cancelled
+    pub fn run(
+        mut self,
+        mut next: impl FnMut(&mut D) -> Option<RecunsResult<I, E>>,
+    ) -> Outcome<Option<D>, E> {
+        loop {
+            if !self.turn() {
+                return self.stop(Ok(None));
+            }
+            if self.parser.redo() {
+                continue;
+            }
+            if self.parser.is_finished() {
+                break;
+            }
+            let c = next(&mut self.parser.state.data);
+            if !self.dispatch(c) {
+                let errors = std::mem::take(&mut self.parser.state.errors);
+                return self.stop(Err(errors));
+            }
+        }
+        self.parser.finish().map(Some)
+    }
+
+    /// Like `run` with inputs from a stream
+    #[cfg(feature = "async")]
+    pub async fn run_async(
+        mut self,
+        mut next: impl Stream<Item = RecunsResult<I, E>> + Unpin,
+    ) -> Outcome<Option<D>, E> {
+        loop {
+            if !self.turn() {
+                return self.stop(Ok(None));
+            }
+            if self.parser.redo() {
+                continue;
+            }
+            if self.parser.is_finished() {
+                break;
+            }
+            let c = poll_fn(|cx| Pin::new(&mut next).poll_next(cx)).await;
+            if !self.dispatch(c) {
+                let errors = std::mem::take(&mut self.parser.state.errors);
+                return self.stop(Err(errors));
+            }
+        }
+        self.parser.finish().map(Some)
+    }
+
+    /// Pull inputs from `next` lazily, yielding what `yields` takes out of the data after every step
+    ///
+    /// Errors are moved into `errors` as they happen, warnings into `warnings` once no alternative
+    /// can roll them back
+    pub fn iter<U: 'a>(
+        self,
+        errors: &'a mut Vec<Diagnostic<E>>,
+        warnings: &'a mut Vec<Diagnostic<E>>,
+        next: impl 'a + FnMut(&mut D) -> Option<RecunsResult<I, E>>,
+        yields: impl 'a + FnMut(&mut D) -> Option<VecDeque<U>>,
+    ) -> impl 'a + Iterator<Item = U>
+    where
+        D: 'a,
+        F: 'a,
+    {
+        DriverIter {
+            steps: Steps::new(self, next, batches(yields)),
+            errors,
+            warnings,
+        }
+    }
+
+    /// Like `iter` with errors returned in order among the values, warnings are taken
+    /// with `TakeWarnings`
+    ///
+    /// Stops after the first error when `stop_when_err` is set, otherwise continues after each one
+    pub fn try_iter<U: 'a>(
+        self,
+        next: impl 'a + FnMut(&mut D) -> Option<RecunsResult<I, E>>,
+        yields: impl 'a + FnMut(&mut D) -> Option<VecDeque<U>>,
+    ) -> impl 'a + Iterator<Item = Result<U, Diagnostic<E>>> + TakeWarnings<E>
+    where
+        D: 'a,
+        F: 'a,
+    {
+        TryIter {
+            steps: Steps::new(self, next, batches(yields)),
+            errors: VecDeque::new(),
+            held: None,
+        }
+    }
+
+    /// Like `iter` with the values frames emit with `RecunsFlow::Yield`
+    ///
+    /// A frame yielding a value that is not a `U` stops the run with `RecunsError::YieldType`
+    pub fn iter_yielded<U: Any>(
+        self,
+        errors: &'a mut Vec<Diagnostic<E>>,
+        warnings: &'a mut Vec<Diagnostic<E>>,
+        next: impl 'a + FnMut(&mut D) -> Option<RecunsResult<I, E>>,
+    ) -> impl 'a + Iterator<Item = U>
+    where
+        D: 'a,
+        F: 'a,
+    {
+        DriverIter {
+            steps: Steps::new(self, next, yielded),
+            errors,
+            warnings,
+        }
+    }
+
+    /// Like `try_iter` with the values frames emit with `RecunsFlow::Yield`
+    ///
+    /// A frame yielding a value that is not a `U` stops the run with `RecunsError::YieldType`
+    pub fn try_iter_yielded<U: Any>(
+        self,
+        next: impl 'a + FnMut(&mut D) -> Option<RecunsResult<I, E>>,
+    ) -> impl 'a + Iterator<Item = Result<U, Diagnostic<E>>> + TakeWarnings<E>
+    where
+        D: 'a,
+        F: 'a,
+    {
+        TryIter {
+            steps: Steps::new(self, next, yielded),
+            errors: VecDeque::new(),
+            held: None,
+        }
+    }
+
+    /// Like `iter` with inputs from a stream
+    #[cfg(feature = "async")]
+    pub fn iter_async<U: 'a>(
+        self,
+        errors: &'a mut Vec<Diagnostic<E>>,
+        warnings: &'a mut Vec<Diagnostic<E>>,
+        next: impl 'a + Stream<Item = RecunsResult<I, E>> + Unpin,
+        yields: impl 'a + FnMut(&mut D) -> Option<VecDeque<U>>,
+    ) -> impl 'a + Stream<Item = U>
+    where
+        D: 'a,
+        F: 'a,
+    {
+        DriverIter {
+            steps: Steps::new(self, next, batches(yields)),
+            errors,
+            warnings,
+        }
+    }
+
+    /// End a run before the parser finished
+    #[inline]
+    fn stop<T>(mut self, result: RecunsResultErrs<T, E>) -> Outcome<T, E> {
+        Outcome {
+            result,
+            warnings: self.parser.state.settled_warnings(),
+        }
+    }
+
+    /// Run `cancel` and `on_loop`, `false` if cancelled
+    #[inline]
+    fn turn(&mut self) -> bool {
+        if let Some(cancel) = &mut self.cancel {
+            if cancel() {
+                return false;
+            }
+        }
+        if let Some(on_loop) = &mut self.on_loop {
+            on_loop(&mut self.parser.state);
+        }
+        true
+    }
+
+    /// Dispatch a pulled input, `false` if pulling it failed
+    #[inline]
+    fn dispatch(&mut self, c: Option<RecunsResult<I, E>>) -> bool {
+        match c {
+            None => self.parser.dispatch_eof(),
+            Some(Ok(c)) => self.parser.dispatch(c),
+            Some(Err(err)) => {
+                self.parser.state.input_error(err);
+                self.parser.stopped = true;
+                return false;
+            }
+        }
+        true
+    }
+}
+impl<'a, I, D, E, F> Debug for Driver<'a, I, D, E, F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Driver")
+            .field("cancel", &self.cancel.is_some())
+            .field("on_loop", &self.on_loop.is_some())
+            .finish()
+    }
+}
+
+/// The stepping shared by the iterators of `Driver`, `S` pulls the inputs and `T` takes the outputs
+struct Steps<'a, I, D, E, F, S, T> {
+    driver: Driver<'a, I, D, E, F>,
+    next: S,
+    take: T,
+}
+impl<'a, I, D, E, F, S, T> Steps<'a, I, D, E, F, S, T> {
+    #[inline]
+    fn new(driver: Driver<'a, I, D, E, F>, next: S, take: T) -> Self {
+        Self { driver, next, take }
+    }
+
+    /// Errors raised so far, they all come before the value returned next
+    #[inline]
+    fn errors(&mut self) -> &mut Vec<Diagnostic<E>> {
+        &mut self.driver.parser.state.errors
+    }
+    /// Move the errors and the warnings no alternative can roll back anymore out
+    #[inline]
+    fn drain(&mut self, errors: &mut Vec<Diagnostic<E>>, warnings: &mut Vec<Diagnostic<E>>) {
+        errors.append(self.errors());
+        self.driver.parser.state.take_warnings(warnings);
+    }
+}
+
+/// Take the values a `yields` callback moves out of the data, it is asked once per step
+/// and not while an alternative can still fail and roll the data back
+fn batches<'a, I, D, E, F, U>(
+    mut yields: impl 'a + FnMut(&mut D) -> Option<VecDeque<U>>,
+) -> impl 'a + FnMut(&mut Parser<'a, I, D, E, F>) -> Option<U>
+where
+    I: 'a,
+    D: 'a,
+    F: 'a,
+    U: 'a,
+{
+    let mut is_yield = false;
+    let mut yield_val = VecDeque::new();
+    move |p| {
+        let s = &mut p.state;
+        if let Some(v) = yield_val.pop_front() {
+            return Some(v);
+        }
+        if !is_yield && s.alts.is_empty() {
+            if let Some(mut v) = yields(&mut s.data) {
+                is_yield = true;
+                if let Some(rv) = v.pop_front() {
+                    yield_val = v;
+                    return Some(rv);
+                }
+            }
+        }
+        is_yield = false;
+        None
+    }
+}
+
+/// Take the values frames emit with `RecunsFlow::Yield`, stopping the parser at one that is not a `U`
+fn yielded<I, D, E: From<RecunsError>, F, U: Any>(p: &mut Parser<'_, I, D, E, F>) -> Option<U> {
+    let v = p.state.take_yield()?;
+    match v.downcast() {
+        Ok(v) => Some(*v),
+        Err(_) => {
+            let d = p
+                .state
+                .locate(RecunsError::YieldType(type_name::<U>()).into());
+            p.state.errors.push(d);
+            p.stopped = true;
+            None
+        }
+    }
+}
+
+impl<'a, I: 'a, D, E, F, U, S, T> Steps<'a, I, D, E, F, S, T>
+where
+    E: From<RecunsError> + 'a,
+    F: Frames<Input = I, Data = D, Error = E>,
+    S: FnMut(&mut D) -> Option<RecunsResult<I, E>>,
+    T: FnMut(&mut Parser<'a, I, D, E, F>) -> Option<U>,
+{
+    /// Run until a value is yielded, `None` at the end or when cancelled
+    fn step(&mut self) -> Option<U> {
+        loop {
+            if !self.driver.turn() {
+                return None;
+            }
+            if let Some(v) = (self.take)(&mut self.driver.parser) {
+                return Some(v);
+            }
+            if self.driver.parser.redo() {
+                continue;
+            }
+            if self.driver.parser.is_finished() {
+                return None;
+            }
+            let c = (self.next)(&mut self.driver.parser.state.data);
+            if !self.driver.dispatch(c) {
+                return None;
+            }
+        }
+    }
+}
+#[cfg(feature = "async")]
+impl<'a, I: 'a, D, E, F, U, S, T> Steps<'a, I, D, E, F, S, T>
+where
+    E: From<RecunsError> + 'a,
+    F: Frames<Input = I, Data = D, Error = E>,
+    S: Stream<Item = RecunsResult<I, E>> + Unpin,
+    T: FnMut(&mut Parser<'a, I, D, E, F>) -> Option<U>,
+{
+    /// Like `step` with inputs from a stream
+    fn poll_step(&mut self, cx: &mut Context<'_>) -> Poll<Option<U>> {
+        loop {
+            if !self.driver.turn() {
+                return Poll::Ready(None);
+            }
+            if let Some(v) = (self.take)(&mut self.driver.parser) {
+                return Poll::Ready(Some(v));
+            }
+            if self.driver.parser.redo() {
+                continue;
+            }
+            if self.driver.parser.is_finished() {
+                return Poll::Ready(None);
+            }
+            match Pin::new(&mut self.next).poll_next(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(c) => {
+                    if !self.driver.dispatch(c) {
+                        return Poll::Ready(None);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// What `Driver::iter` and `Driver::iter_async` return
+struct DriverIter<'a, I, D, E, F, S, T> {
+    steps: Steps<'a, I, D, E, F, S, T>,
+    errors: &'a mut Vec<Diagnostic<E>>,
+    warnings: &'a mut Vec<Diagnostic<E>>,
+}
+impl<'a, I: 'a, D, E, F, U, S, T> Iterator for DriverIter<'a, I, D, E, F, S, T>
+where
+    E: From<RecunsError> + 'a,
+    F: Frames<Input = I, Data = D, Error = E>,
+    S: FnMut(&mut D) -> Option<RecunsResult<I, E>>,
+    T: FnMut(&mut Parser<'a, I, D, E, F>) -> Option<U>,
+{
+    type Item = U;
+
+    fn next(&mut self) -> Option<U> {
+        let r = self.steps.step();
+        self.steps.drain(self.errors, self.warnings);
+        r
+    }
+}
+// No field is structurally pinned
+#[cfg(feature = "async")]
+impl<'a, I, D, E, F, S, T> Unpin for DriverIter<'a, I, D, E, F, S, T> {}
+#[cfg(feature = "async")]
+impl<'a, I: 'a, D, E, F, U, S, T> Stream for DriverIter<'a, I, D, E, F, S, T>
+where
+    E: From<RecunsError> + 'a,
+    F: Frames<Input = I, Data = D, Error = E>,
+    S: Stream<Item = RecunsResult<I, E>> + Unpin,
+    T: FnMut(&mut Parser<'a, I, D, E, F>) -> Option<U>,
+{
+    type Item = U;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<U>> {
+        let this = self.get_mut();
+        let r = this.steps.poll_step(cx);
+        if r.is_ready() {
+            this.steps.drain(this.errors, this.warnings);
+        }
+        r
+    }
+}
+
+/// What `Driver::try_iter` returns
+struct TryIter<'a, I, D, E, F, U, S, T> {
+    steps: Steps<'a, I, D, E, F, S, T>,
+    errors: VecDeque<Diagnostic<E>>,
+    held: Option<U>,
+}
+impl<'a, I: 'a, D, E, F, U, S, T> Iterator for TryIter<'a, I, D, E, F, U, S, T>
+where
+    E: From<RecunsError> + 'a,
+    F: Frames<Input = I, Data = D, Error = E>,
+    S: FnMut(&mut D) -> Option<RecunsResult<I, E>>,
+    T: FnMut(&mut Parser<'a, I, D, E, F>) -> Option<U>,
+{
+    type Item = Result<U, Diagnostic<E>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(e) = self.errors.pop_front() {
+                return Some(Err(e));
+            }
+            if let Some(v) = self.held.take() {
+                return Some(Ok(v));
+            }
+            // Hold the value back until the errors raised before it are out
+            let r = self.steps.step();
+            self.errors.extend(self.steps.errors().drain(..));
+            match r {
+                Some(v) if self.errors.is_empty() => return Some(Ok(v)),
+                Some(v) => self.held = Some(v),
+                None if self.errors.is_empty() => return None,
+                None => {}
+            }
+        }
+    }
+}
+impl<'a, I, D, E, F, U, S, T> TakeWarnings<E> for TryIter<'a, I, D, E, F, U, S, T> {
+    fn take_warnings(&mut self) -> Vec<Diagnostic<E>> {
+        let mut out = vec![];
+        self.steps.driver.parser.state.take_warnings(&mut out);
+        out
+    }
+}
+
+/// Warnings raised by an iterator of `Driver` that returns its errors among the values
+pub trait TakeWarnings<E> {
+    /// Move out the warnings raised so far that no alternative can roll back anymore
+    fn take_warnings(&mut self) -> Vec<Diagnostic<E>>;
+}
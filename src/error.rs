@@ -0,0 +1,81 @@
+use crate::Diagnostic;
+use anyhow::Error;
+use std::fmt;
+use std::sync::Arc;
+
+/// Errors raised by the driver itself rather than by a frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecunsError {
+    /// `RecunsFlow::Fail` was returned while no `RecunsFlow::Alt` is pending
+    FailWithoutAlt,
+    /// Every alternative of a `RecunsFlow::Alt` failed
+    NoAlt(Vec<&'static str>),
+    /// `RecunsFlow::Peek` was returned by a frame without `on_peek`
+    PeekUnhandled,
+    /// `RecunsFlow::Peek` was returned from `on_eof`
+    PeekAtEof,
+    /// A frame was pushed past `State::max_depth`
+    MaxDepth(usize),
+    /// `State::fuel` ran out
+    OutOfFuel,
+    /// An input was redone more than `State::max_redo` times without progress, through these frames
+    Spin(Vec<&'static str>),
+    /// A frame returned `RecunsFlow::None` at the end of input or doesn't handle it
+    UnfinishedAtEof,
+    /// Nothing matched, these are what `RecunsFlow::Expect` said would have been accepted
+    Expected(Vec<&'static str>),
+    /// A frame yielded a value that is not of the driver's output type, named here
+    YieldType(&'static str),
+}
+impl fmt::Display for RecunsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::FailWithoutAlt => write!(f, "Fail without any pending alternative"),
+            Self::NoAlt(names) => write!(f, "No alternative matched <{}>", names.join(" | ")),
+            Self::PeekUnhandled => write!(f, "Peek without on_peek"),
+            Self::PeekAtEof => write!(f, "Peek at the end of input"),
+            Self::MaxDepth(max) => write!(f, "Exceeded the maximum depth of {} frames", max),
+            Self::OutOfFuel => write!(f, "Ran out of fuel"),
+            Self::UnfinishedAtEof => write!(f, "Unfinished at the end of input"),
+            Self::Spin(names) => write!(
+                f,
+                "Input redone without progress through <{}>",
+                names.join(", ")
+            ),
+            Self::Expected(set) if set.len() == 1 => write!(f, "Expected {}", set[0]),
+            Self::Expected(set) => write!(f, "Expected one of {}", set.join(", ")),
+            Self::YieldType(name) => write!(f, "Yielded a value that is not a <{}>", name),
+        }
+    }
+}
+impl RecunsError {
+    /// The stable code of the error, set on its `Diagnostic`
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::FailWithoutAlt => "R0001",
+            Self::NoAlt(_) => "R0002",
+            Self::PeekUnhandled => "R0003",
+            Self::PeekAtEof => "R0004",
+            Self::MaxDepth(_) => "R0005",
+            Self::OutOfFuel => "R0006",
+            Self::Spin(_) => "R0007",
+            Self::UnfinishedAtEof => "R0008",
+            Self::Expected(_) => "R0009",
+            Self::YieldType(_) => "R0010",
+        }
+    }
+}
+impl std::error::Error for RecunsError {}
+impl From<RecunsError> for Arc<Error> {
+    #[inline]
+    fn from(e: RecunsError) -> Self {
+        Arc::new(Error::new(e))
+    }
+}
+impl<E: From<RecunsError>> From<RecunsError> for Diagnostic<E> {
+    #[inline]
+    fn from(e: RecunsError) -> Self {
+        let code = e.code();
+        Diagnostic::error(e.into()).code(code)
+    }
+}
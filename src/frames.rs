@@ -0,0 +1,129 @@
+use crate::*;
+use std::any::{Any, TypeId};
+
+/// A frame kept inline on the `State` stack
+///
+/// Boxed `Recuns` frames implement it with dynamic dispatch, an enum of frames can implement it
+/// to be stored without boxing and checked without virtual calls
+pub trait Frames: Sized {
+    type Input;
+    type Data;
+    type Error: From<RecunsError>;
+
+    fn check(
+        &mut self,
+        input: &Self::Input,
+        data: &mut Self::Data,
+    ) -> RecunsFlow<Self::Input, Self::Data, Self::Error, Self>;
+
+    /// See `Recuns::on_eof`
+    #[inline]
+    fn on_eof(
+        &mut self,
+        data: &mut Self::Data,
+    ) -> RecunsFlow<Self::Input, Self::Data, Self::Error, Self> {
+        let _ = data;
+        RecunsFlow::Err(RecunsError::UnfinishedAtEof.into())
+    }
+
+    /// See `Recuns::on_ret`
+    #[inline]
+    fn on_ret(
+        &mut self,
+        value: Box<dyn Any>,
+        data: &mut Self::Data,
+    ) -> RecunsFlow<Self::Input, Self::Data, Self::Error, Self> {
+        let _ = (value, data);
+        RecunsFlow::None
+    }
+
+    /// See `Recuns::on_peek`
+    #[inline]
+    fn on_peek(
+        &mut self,
+        input: &Self::Input,
+        ahead: &mut Lookahead<Self::Input>,
+        data: &mut Self::Data,
+    ) -> RecunsFlow<Self::Input, Self::Data, Self::Error, Self> {
+        let _ = (input, ahead, data);
+        RecunsFlow::Err(RecunsError::PeekUnhandled.into())
+    }
+
+    /// See `Recuns::sync`
+    #[inline]
+    fn sync(&mut self, input: &Self::Input, data: &mut Self::Data) -> bool {
+        let _ = (input, data);
+        false
+    }
+
+    /// See `Recuns::reset`
+    #[inline]
+    fn reset(&mut self) -> bool {
+        false
+    }
+
+    /// See `Recuns::fork`
+    #[inline]
+    fn fork(&self) -> Option<Self> {
+        None
+    }
+}
+
+/// A frame type to take from the pool, made with `new` when none was recycled
+pub struct Pooled<F> {
+    pub(crate) id: TypeId,
+    pub(crate) new: fn() -> F,
+}
+impl<F> Pooled<F> {
+    /// Frames are pooled by `id`, every frame `new` makes must reset to the same kind of frame
+    #[inline]
+    pub fn new(id: TypeId, new: fn() -> F) -> Self {
+        Self { id, new }
+    }
+}
+impl<I, D, E: From<RecunsError>> Pooled<RecunsBox<I, D, E>> {
+    #[inline]
+    pub fn of<R: Recuns<E, Input = I, Data = D> + Default + 'static>() -> Self {
+        Self::new(TypeId::of::<R>(), || Box::new(R::default()))
+    }
+}
+
+impl<'a, I, D, E: From<RecunsError>> Frames for Box<dyn 'a + Recuns<E, Input = I, Data = D>> {
+    type Input = I;
+    type Data = D;
+    type Error = E;
+
+    #[inline]
+    fn check(&mut self, input: &I, data: &mut D) -> RecunsFlow<I, D, E, Self> {
+        (**self).check(input, data)
+    }
+    #[inline]
+    fn on_eof(&mut self, data: &mut D) -> RecunsFlow<I, D, E, Self> {
+        (**self).on_eof(data)
+    }
+    #[inline]
+    fn on_ret(&mut self, value: Box<dyn Any>, data: &mut D) -> RecunsFlow<I, D, E, Self> {
+        (**self).on_ret(value, data)
+    }
+    #[inline]
+    fn on_peek(
+        &mut self,
+        input: &I,
+        ahead: &mut Lookahead<I>,
+        data: &mut D,
+    ) -> RecunsFlow<I, D, E, Self> {
+        (**self).on_peek(input, ahead, data)
+    }
+    #[inline]
+    fn sync(&mut self, input: &I, data: &mut D) -> bool {
+        (**self).sync(input, data)
+    }
+    #[inline]
+    fn reset(&mut self) -> bool {
+        (**self).reset()
+    }
+    #[inline]
+    fn fork(&self) -> Option<Self> {
+        (**self).fork().map(|r| r as Self)
+    }
+}
@@ -1,38 +1,138 @@
+mod diagnostic;
+mod driver;
+mod error;
+mod frames;
+mod parser;
 pub mod recuns_of;
+mod render;
 mod state;
 use anyhow::Error;
+pub use diagnostic::*;
+pub use driver::*;
+pub use error::*;
+pub use frames::*;
+pub use parser::*;
 pub use recuns_of::*;
+pub use render::*;
 pub use state::*;
+use std::any::Any;
+use std::marker::PhantomData;
+use std::ops::Range;
 use std::sync::*;
 
 #[cfg(test)]
 mod test_json;
 
-pub trait Recuns {
+/// A frame of the parse, `E` is the error type its flows carry
+pub trait Recuns<E: From<RecunsError> = Arc<Error>> {
     type Input;
     type Data;
 
     fn check(
         &mut self,
-        input: Self::Input,
+        input: &Self::Input,
         data: &mut Self::Data,
-        eof: bool,
-    ) -> RecunsFlow<Self::Input, Self::Data>;
+    ) -> RecunsFlow<Self::Input, Self::Data, E>;
+
+    /// At the end of input every frame on the stack gets `on_eof` from the top down,
+    /// a frame must end or fail there, returning `RecunsFlow::None` is an error and so is not
+    /// overriding it
+    #[inline]
+    fn on_eof(&mut self, data: &mut Self::Data) -> RecunsFlow<Self::Input, Self::Data, E> {
+        let _ = data;
+        RecunsFlow::Err(RecunsError::UnfinishedAtEof.into())
+    }
+
+    /// Receives the value a child frame ended with, the returned flow is applied like one from `check`
+    #[inline]
+    fn on_ret(
+        &mut self,
+        value: Box<dyn Any>,
+        data: &mut Self::Data,
+    ) -> RecunsFlow<Self::Input, Self::Data, E> {
+        let _ = (value, data);
+        RecunsFlow::None
+    }
+
+    /// Receives the input that asked for `RecunsFlow::Peek` once the inputs after it are read ahead
+    #[inline]
+    fn on_peek(
+        &mut self,
+        input: &Self::Input,
+        ahead: &mut Lookahead<Self::Input>,
+        data: &mut Self::Data,
+    ) -> RecunsFlow<Self::Input, Self::Data, E> {
+        let _ = (input, ahead, data);
+        RecunsFlow::Err(RecunsError::PeekUnhandled.into())
+    }
+
+    /// Whether the frame can pick up again at this input after an error in it or above it,
+    /// see `State::recover`
+    #[inline]
+    fn sync(&mut self, input: &Self::Input, data: &mut Self::Data) -> bool {
+        let _ = (input, data);
+        false
+    }
+
+    /// Clear the frame so the pool can hand it out as a new one, `false` if it can't be reused
+    #[inline]
+    fn reset(&mut self) -> bool {
+        false
+    }
+
+    /// Clone this frame for `State::checkpoint`, `None` if it can't be cloned
+    #[inline]
+    fn fork(&self) -> Option<RecunsBox<Self::Input, Self::Data, E>> {
+        None
+    }
 }
-pub type RecunsResult<T> = Result<T, Arc<Error>>;
-pub type RecunsResultErrs<T> = Result<T, Vec<Arc<Error>>>;
+pub type RecunsResult<T, E = Arc<Error>> = Result<T, E>;
+pub type RecunsResultErrs<T, E = Arc<Error>> = Result<T, Vec<Diagnostic<E>>>;
+pub type RecunsBox<I, D, E = Arc<Error>> = Box<dyn Recuns<E, Input = I, Data = D>>;
+/// The default frame type of `State`
+pub type RecunsFrame<'a, I, D, E = Arc<Error>> = Box<dyn 'a + Recuns<E, Input = I, Data = D>>;
+pub type RecunsAlts<F> = Vec<(F, &'static str)>;
 
-pub enum RecunsFlow<I, D> {
+/// What a frame asks the driver to do, `E` is the error type and `F` the frame type kept on the stack
+pub enum RecunsFlow<I, D, E = Arc<Error>, F = RecunsBox<I, D, E>> {
     None,
     End,
     EndReDo,
-    Call(Box<dyn Recuns<Input = I, Data = D>>, &'static str),
-    CallNext(Box<dyn Recuns<Input = I, Data = D>>, &'static str),
-    Mov(Box<dyn Recuns<Input = I, Data = D>>, &'static str),
-    MovNext(Box<dyn Recuns<Input = I, Data = D>>, &'static str),
-    Err(Arc<Error>),
+    Call(F, &'static str),
+    CallNext(F, &'static str),
+    Mov(F, &'static str),
+    MovNext(F, &'static str),
+    /// Like `Call` with a frame reused from the pool when pooling is on
+    CallPooled(Pooled<F>, &'static str),
+    /// Like `CallNext` with a frame reused from the pool when pooling is on
+    CallNextPooled(Pooled<F>, &'static str),
+    /// End and hand the value to the parent frame's `on_ret`
+    Ret(Box<dyn Any>),
+    /// Like `Ret`, then redo the input on the parent frame
+    RetReDo(Box<dyn Any>),
+    /// Hand a value to the driver's output, then apply the boxed flow, see `Driver::iter_yielded`
+    Yield(Box<dyn Any>, Box<Self>),
+    /// Try each frame in order as a `Call`, rewinding data and input to here when one of them `Fail`s
+    ///
+    /// Built with `RecunsFlow::alt` and `or`, the marker ties the flow to its input type
+    Alt(RecunsAlts<F>, fn(&D) -> D, PhantomData<fn() -> I>),
+    /// The current alternative does not match, try the next one of the nearest `Alt`
+    Fail,
+    /// Note what would have been accepted at the current input, then apply the boxed flow
+    ///
+    /// What frames and alternatives expect at the furthest input one of them failed on is merged
+    /// into `RecunsError::Expected` in place of `FailWithoutAlt` or `NoAlt`, with the span
+    /// of the first one that has one
+    Expect(Vec<&'static str>, Option<Range<usize>>, Box<Self>),
+    /// Read ahead this many inputs and hand them with the same input to `on_peek`
+    Peek(usize),
+    Err(E),
+    /// Like `Err` with spans, labels, a code and notes, see `Diagnostic::error`
+    Diag(Box<Diagnostic<E>>),
+    /// Record a non-fatal diagnostic in `State::warnings`, then apply the boxed flow
+    Warn(Box<Diagnostic<E>>, Box<Self>),
 }
-impl<I, D> std::fmt::Debug for RecunsFlow<I, D> {
+impl<I, D, E: std::fmt::Debug, F> std::fmt::Debug for RecunsFlow<I, D, E, F> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::None => write!(f, "None"),
@@ -42,101 +142,1105 @@ impl<I, D> std::fmt::Debug for RecunsFlow<I, D> {
             Self::CallNext(_, name) => write!(f, "CallNext({})", name),
             Self::Mov(_, name) => write!(f, "Mov({})", name),
             Self::MovNext(_, name) => write!(f, "MovNext({})", name),
+            Self::CallPooled(_, name) => write!(f, "CallPooled({})", name),
+            Self::CallNextPooled(_, name) => write!(f, "CallNextPooled({})", name),
+            Self::Ret(_) => write!(f, "Ret(..)"),
+            Self::RetReDo(_) => write!(f, "RetReDo(..)"),
+            Self::Yield(_, then) => write!(f, "Yield(.., {:?})", then),
+            Self::Alt(alts, _, _) => {
+                let names = alts.iter().map(|(_, name)| *name).collect::<Vec<_>>();
+                write!(f, "Alt({})", names.join(" | "))
+            }
+            Self::Fail => write!(f, "Fail"),
+            Self::Expect(set, _, then) => write!(f, "Expect({}, {:?})", set.join(", "), then),
+            Self::Peek(k) => write!(f, "Peek({})", k),
             Self::Err(err) => write!(f, "Err({:?})", err),
+            Self::Diag(d) => write!(f, "Diag({:?})", d.error),
+            Self::Warn(d, then) => write!(f, "Warn({:?}, {:?})", d.error, then),
         }
     }
 }
-impl<I, D> From<Arc<Error>> for RecunsFlow<I, D> {
+impl<I, D, E, F> From<E> for RecunsFlow<I, D, E, F> {
     #[inline]
-    fn from(e: Arc<Error>) -> Self {
+    fn from(e: E) -> Self {
         Self::Err(e)
     }
 }
-impl<I, D> From<Error> for RecunsFlow<I, D> {
+impl<I, D, F> From<Error> for RecunsFlow<I, D, Arc<Error>, F> {
     #[inline]
     fn from(e: Error) -> Self {
         Self::Err(Arc::new(e))
     }
 }
-impl<I, D> RecunsFlow<I, D> {
+impl<I, D, E, F> RecunsFlow<I, D, E, F> {
+    #[inline]
+    pub fn ret(v: impl Any) -> Self {
+        Self::Ret(Box::new(v))
+    }
+    #[inline]
+    pub fn emit(v: impl Any) -> Self {
+        Self::emit_then(v, Self::None)
+    }
+    #[inline]
+    pub fn emit_then(v: impl Any, then: Self) -> Self {
+        Self::Yield(Box::new(v), Box::new(then))
+    }
     #[inline]
-    pub fn call(name: &'static str, r: impl Recuns<Input = I, Data = D> + 'static) -> Self {
+    pub fn warn(d: Diagnostic<E>) -> Self {
+        Self::warn_then(d, Self::None)
+    }
+    #[inline]
+    pub fn warn_then(d: Diagnostic<E>, then: Self) -> Self {
+        Self::Warn(Box::new(d), Box::new(then))
+    }
+    /// `Fail` expecting one of `set`
+    #[inline]
+    pub fn expect(set: impl IntoIterator<Item = &'static str>) -> Self {
+        Self::expect_then(set, Self::Fail)
+    }
+    #[inline]
+    pub fn expect_then(set: impl IntoIterator<Item = &'static str>, then: Self) -> Self {
+        Self::Expect(set.into_iter().collect(), None, Box::new(then))
+    }
+    /// Point an `Expect` at the inputs of `span`, panics if this is not an `Expect`
+    #[inline]
+    pub fn span(mut self, span: Range<usize>) -> Self {
+        match &mut self {
+            Self::Expect(_, at, _) => *at = Some(span),
+            _ => panic!("`span` can only be used on RecunsFlow::Expect"),
+        }
+        self
+    }
+    #[inline]
+    pub fn ret_redo(v: impl Any) -> Self {
+        Self::RetReDo(Box::new(v))
+    }
+    #[inline]
+    pub fn diag(d: Diagnostic<E>) -> Self {
+        Self::Diag(Box::new(d))
+    }
+}
+impl<I, D, E: From<RecunsError>> RecunsFlow<I, D, E> {
+    #[inline]
+    pub fn call(name: &'static str, r: impl Recuns<E, Input = I, Data = D> + 'static) -> Self {
         Self::Call(Box::new(r), name)
     }
     #[inline]
-    pub fn call_next(name: &'static str, r: impl Recuns<Input = I, Data = D> + 'static) -> Self {
+    pub fn call_next(name: &'static str, r: impl Recuns<E, Input = I, Data = D> + 'static) -> Self {
         Self::CallNext(Box::new(r), name)
     }
     #[inline]
-    pub fn mov(name: &'static str, r: impl Recuns<Input = I, Data = D> + 'static) -> Self {
+    pub fn mov(name: &'static str, r: impl Recuns<E, Input = I, Data = D> + 'static) -> Self {
         Self::Mov(Box::new(r), name)
     }
     #[inline]
-    pub fn mov_next(name: &'static str, r: impl Recuns<Input = I, Data = D> + 'static) -> Self {
+    pub fn mov_next(name: &'static str, r: impl Recuns<E, Input = I, Data = D> + 'static) -> Self {
         Self::MovNext(Box::new(r), name)
     }
+    #[inline]
+    pub fn call_pooled<R>(name: &'static str) -> Self
+    where
+        R: Recuns<E, Input = I, Data = D> + Default + 'static,
+    {
+        Self::CallPooled(Pooled::of::<R>(), name)
+    }
+    #[inline]
+    pub fn call_next_pooled<R>(name: &'static str) -> Self
+    where
+        R: Recuns<E, Input = I, Data = D> + Default + 'static,
+    {
+        Self::CallNextPooled(Pooled::of::<R>(), name)
+    }
+    /// Add another alternative, panics if this is not an `Alt`
+    #[inline]
+    pub fn or(
+        mut self,
+        name: &'static str,
+        r: impl Recuns<E, Input = I, Data = D> + 'static,
+    ) -> Self {
+        match &mut self {
+            Self::Alt(alts, _, _) => alts.push((Box::new(r), name)),
+            _ => panic!("`or` can only be used on RecunsFlow::Alt"),
+        }
+        self
+    }
+}
+impl<I, D: Clone, E: From<RecunsError>> RecunsFlow<I, D, E> {
+    #[inline]
+    pub fn alt(name: &'static str, r: impl Recuns<E, Input = I, Data = D> + 'static) -> Self {
+        Self::Alt(vec![(Box::new(r), name)], D::clone, PhantomData)
+    }
 }
 #[doc(hidden)]
-pub trait RecunsEx<I, D> {
-    fn rfcall(self, name: &'static str) -> RecunsFlow<I, D>;
-    fn rfcall_next(self, name: &'static str) -> RecunsFlow<I, D>;
-    fn rfmov(self, name: &'static str) -> RecunsFlow<I, D>;
-    fn rfmov_next(self, name: &'static str) -> RecunsFlow<I, D>;
+pub trait RecunsEx<I, D, E = Arc<Error>> {
+    fn rfcall(self, name: &'static str) -> RecunsFlow<I, D, E>;
+    fn rfcall_next(self, name: &'static str) -> RecunsFlow<I, D, E>;
+    fn rfmov(self, name: &'static str) -> RecunsFlow<I, D, E>;
+    fn rfmov_next(self, name: &'static str) -> RecunsFlow<I, D, E>;
 }
-impl<R: 'static, I, D> RecunsEx<I, D> for R
+impl<R: 'static, I, D, E: From<RecunsError>> RecunsEx<I, D, E> for R
 where
-    R: Recuns<Input = I, Data = D>,
+    R: Recuns<E, Input = I, Data = D>,
 {
     #[inline]
-    fn rfcall(self, name: &'static str) -> RecunsFlow<I, D> {
+    fn rfcall(self, name: &'static str) -> RecunsFlow<I, D, E> {
         RecunsFlow::call(name, self)
     }
     #[inline]
-    fn rfcall_next(self, name: &'static str) -> RecunsFlow<I, D> {
+    fn rfcall_next(self, name: &'static str) -> RecunsFlow<I, D, E> {
         RecunsFlow::call_next(name, self)
     }
     #[inline]
-    fn rfmov(self, name: &'static str) -> RecunsFlow<I, D> {
+    fn rfmov(self, name: &'static str) -> RecunsFlow<I, D, E> {
         RecunsFlow::mov(name, self)
     }
     #[inline]
-    fn rfmov_next(self, name: &'static str) -> RecunsFlow<I, D> {
+    fn rfmov_next(self, name: &'static str) -> RecunsFlow<I, D, E> {
         RecunsFlow::mov_next(name, self)
     }
 }
 #[doc(hidden)]
-pub trait RecunsFnEx<I, D> {
-    fn rfcall(self, name: &'static str) -> RecunsFlow<I, D>;
-    fn rfcall_next(self, name: &'static str) -> RecunsFlow<I, D>;
-    fn rfmov(self, name: &'static str) -> RecunsFlow<I, D>;
-    fn rfmov_next(self, name: &'static str) -> RecunsFlow<I, D>;
+pub trait RecunsFnEx<I, D, E = Arc<Error>> {
+    fn rfcall(self, name: &'static str) -> RecunsFlow<I, D, E>;
+    fn rfcall_next(self, name: &'static str) -> RecunsFlow<I, D, E>;
+    fn rfmov(self, name: &'static str) -> RecunsFlow<I, D, E>;
+    fn rfmov_next(self, name: &'static str) -> RecunsFlow<I, D, E>;
 }
-impl<F: 'static, I: 'static, D: 'static> RecunsFnEx<I, D> for F
+impl<F: 'static, I: 'static, D: 'static, E: From<RecunsError> + 'static> RecunsFnEx<I, D, E> for F
 where
-    F: FnMut(I, &mut D, bool) -> RecunsFlow<I, D>,
+    F: FnMut(Option<&I>, &mut D) -> RecunsFlow<I, D, E>,
 {
     #[inline]
-    fn rfcall(self, name: &'static str) -> RecunsFlow<I, D> {
+    fn rfcall(self, name: &'static str) -> RecunsFlow<I, D, E> {
         RecunsFlow::call(name, self.recuns())
     }
     #[inline]
-    fn rfcall_next(self, name: &'static str) -> RecunsFlow<I, D> {
+    fn rfcall_next(self, name: &'static str) -> RecunsFlow<I, D, E> {
         RecunsFlow::call_next(name, self.recuns())
     }
     #[inline]
-    fn rfmov(self, name: &'static str) -> RecunsFlow<I, D> {
+    fn rfmov(self, name: &'static str) -> RecunsFlow<I, D, E> {
         RecunsFlow::mov(name, self.recuns())
     }
     #[inline]
-    fn rfmov_next(self, name: &'static str) -> RecunsFlow<I, D> {
+    fn rfmov_next(self, name: &'static str) -> RecunsFlow<I, D, E> {
         RecunsFlow::mov_next(name, self.recuns())
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    struct Words;
+    impl Recuns for Words {
+        type Input = char;
+        type Data = Vec<String>;
+
+        fn check(&mut self, &c: &char, _: &mut Vec<String>) -> RecunsFlow<char, Vec<String>> {
+            if c == ' ' {
+                return RecunsFlow::None;
+            }
+            RecunsFlow::alt("ab", Lit("ab", 0))
+                .or("ac", Lit("ac", 0))
+                .or("a", Lit("a", 0))
+        }
+        fn on_ret(
+            &mut self,
+            v: Box<dyn Any>,
+            data: &mut Vec<String>,
+        ) -> RecunsFlow<char, Vec<String>> {
+            data.push(v.downcast::<&str>().unwrap().to_string());
+            RecunsFlow::None
+        }
+        fn on_eof(&mut self, _: &mut Vec<String>) -> RecunsFlow<char, Vec<String>> {
+            RecunsFlow::End
+        }
+    }
+    struct Lit(&'static str, usize);
+    impl Recuns for Lit {
+        type Input = char;
+        type Data = Vec<String>;
+
+        fn check(&mut self, &c: &char, _: &mut Vec<String>) -> RecunsFlow<char, Vec<String>> {
+            if !self.0[self.1..].starts_with(c) {
+                return RecunsFlow::Fail;
+            }
+            self.1 += c.len_utf8();
+            if self.1 == self.0.len() {
+                return RecunsFlow::ret(self.0);
+            }
+            RecunsFlow::None
+        }
+        fn on_eof(&mut self, _: &mut Vec<String>) -> RecunsFlow<char, Vec<String>> {
+            RecunsFlow::Fail
+        }
+    }
+
+    #[test]
+    fn test_alt() {
+        let mut code = "ac ab a aab".chars();
+        let r = do_loop(vec![], Words, true, |_| code.next().map(Ok))
+            .result
+            .unwrap();
+        assert_eq!(r.unwrap(), vec!["ac", "ab", "a", "a", "ab"]);
+
+        let mut code = "ab b".chars();
+        let r = do_loop(vec![], Words, true, |_| code.next().map(Ok))
+            .result
+            .unwrap_err();
+        assert_eq!(r.len(), 1);
+        assert_eq!(
+            r[0].downcast_ref::<RecunsError>(),
+            Some(&RecunsError::NoAlt(vec!["ab", "ac", "a"]))
+        );
+        assert_eq!(r[0].code, Some("R0002"));
+    }
+
+    /// `ab` or `ac` then `;`
+    struct Stmt(bool);
+    impl Recuns for Stmt {
+        type Input = char;
+        type Data = ();
+
+        fn check(&mut self, &c: &char, _: &mut ()) -> RecunsFlow<char, ()> {
+            match (self.0, c) {
+                (false, _) => RecunsFlow::alt("ab", ExpLit("ab", 0)).or("ac", ExpLit("ac", 0)),
+                (true, ';') => RecunsFlow::End,
+                (true, _) => RecunsFlow::expect(Some(";")),
+            }
+        }
+        fn on_ret(&mut self, _: Box<dyn Any>, _: &mut ()) -> RecunsFlow<char, ()> {
+            self.0 = true;
+            RecunsFlow::None
+        }
+    }
+    /// A literal with an optional `?` after it
+    struct ExpLit(&'static str, usize);
+    impl Recuns for ExpLit {
+        type Input = char;
+        type Data = ();
+
+        fn check(&mut self, &c: &char, _: &mut ()) -> RecunsFlow<char, ()> {
+            if self.1 == self.0.len() {
+                if c == '?' {
+                    return RecunsFlow::ret(());
+                }
+                return RecunsFlow::expect_then(Some("?"), RecunsFlow::ret_redo(()));
+            }
+            let want = &self.0[self.1..self.1 + 1];
+            if !want.starts_with(c) {
+                return RecunsFlow::expect(Some(want));
+            }
+            self.1 += 1;
+            RecunsFlow::None
+        }
+    }
+
+    #[test]
+    fn test_expect() {
+        let parse = |code: &str| {
+            let mut code = code.chars();
+            do_loop((), Stmt(false), true, |_| code.next().map(Ok)).result
+        };
+        assert!(parse("ac?;").is_ok());
+
+        let e = parse("ad;").unwrap_err();
+        assert_eq!(e.len(), 1);
+        assert_eq!(
+            e[0].downcast_ref::<RecunsError>(),
+            Some(&RecunsError::Expected(vec!["b", "c"]))
+        );
+        assert_eq!(e[0].error.to_string(), "Expected one of b, c");
+        assert_eq!(e[0].code, Some("R0009"));
+        assert_eq!(e[0].index, 1);
+
+        let e = parse("ab!").unwrap_err();
+        assert_eq!(e[0].error.to_string(), "Expected one of ?, ;");
+        assert_eq!(e[0].index, 2);
+
+        let e = parse("ab?!").unwrap_err();
+        assert_eq!(e[0].error.to_string(), "Expected ;");
+        assert_eq!(e[0].stack, vec!["root"]);
+
+        // Frames without an `on_eof` are unfinished at the end of input
+        for code in ["a", "ab?"] {
+            let e = parse(code).unwrap_err();
+            assert_eq!(e.len(), 1);
+            assert!(e[0].eof);
+            assert_eq!(
+                e[0].downcast_ref::<RecunsError>(),
+                Some(&RecunsError::UnfinishedAtEof)
+            );
+        }
+    }
+
+    /// Warns that `ab` is deprecated once it starts matching
+    struct Old;
+    impl Recuns for Old {
+        type Input = char;
+        type Data = Vec<String>;
+
+        fn check(&mut self, &c: &char, _: &mut Vec<String>) -> RecunsFlow<char, Vec<String>> {
+            if c == ' ' {
+                return RecunsFlow::None;
+            }
+            RecunsFlow::alt("ab", Deprecated(Lit("ab", 0))).or("ac", Lit("ac", 0))
+        }
+        fn on_ret(
+            &mut self,
+            v: Box<dyn Any>,
+            data: &mut Vec<String>,
+        ) -> RecunsFlow<char, Vec<String>> {
+            data.push(v.downcast::<&str>().unwrap().to_string());
+            RecunsFlow::None
+        }
+        fn on_eof(&mut self, _: &mut Vec<String>) -> RecunsFlow<char, Vec<String>> {
+            RecunsFlow::End
+        }
+    }
+    struct Deprecated(Lit);
+    impl Recuns for Deprecated {
+        type Input = char;
+        type Data = Vec<String>;
+
+        fn check(&mut self, c: &char, data: &mut Vec<String>) -> RecunsFlow<char, Vec<String>> {
+            let first = self.0 .1 == 0;
+            let r = self.0.check(c, data);
+            if first && !matches!(r, RecunsFlow::Fail) {
+                let e = anyhow::anyhow!("`{}` is deprecated", self.0 .0);
+                return RecunsFlow::warn_then(Diagnostic::warning(Arc::new(e)), r);
+            }
+            r
+        }
+        fn on_eof(&mut self, data: &mut Vec<String>) -> RecunsFlow<char, Vec<String>> {
+            self.0.on_eof(data)
+        }
+    }
+
+    #[test]
+    fn test_warn() {
+        let mut code = "ac ab".chars();
+        let Outcome { result, warnings } = Driver::new(vec![], Old).run(|_| code.next().map(Ok));
+        assert_eq!(result.unwrap().unwrap(), vec!["ac", "ab"]);
+        // The one raised by the failed alternative of `ac` was rolled back
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].index, 3);
+        assert_eq!(warnings[0].stack, vec!["root", "ab"]);
+        assert_eq!(warnings[0].error.to_string(), "`ab` is deprecated");
+
+        let mut p = Parser::new(vec![], Old, true);
+        assert!(p.feed_all("ab".chars()));
+        assert_eq!(p.state().warnings.len(), 1);
+        assert_eq!(p.state().warnings[0].severity, Severity::Warning);
+
+        // Stopping while `ab` can still fail drops its warning
+        let mut p = Parser::new(vec![], Old, true).fuel(2);
+        assert!(!p.feed_all("ab".chars()));
+        let r = p.finish();
+        assert!(r.result.is_err());
+        assert!(r.warnings.is_empty());
+
+        let mut code = "ab".chars();
+        let mut errors = vec![];
+        let mut warnings = vec![];
+        let words: Vec<_> = do_iter(
+            vec![],
+            Old,
+            true,
+            &mut errors,
+            &mut warnings,
+            move |_| code.next().map(Ok),
+            |d: &mut Vec<String>| Some(d.drain(..).collect()),
+        )
+        .collect();
+        assert_eq!(words, vec!["ab"]);
+        assert!(errors.is_empty());
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].severity, Severity::Warning);
+
+        let mut code = "ac ab".chars();
+        let mut words = Driver::new(vec![], Old).try_iter(
+            |_| code.next().map(Ok),
+            |d: &mut Vec<String>| Some(d.drain(..).collect()),
+        );
+        let ok = words.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(ok, vec!["ac", "ab"]);
+        let warnings = words.take_warnings();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].index, 3);
+        assert!(words.take_warnings().is_empty());
+    }
+
+    static WORDS_MADE: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+    struct Spaced;
+    impl Recuns for Spaced {
+        type Input = char;
+        type Data = Vec<String>;
+
+        fn check(&mut self, &c: &char, _: &mut Vec<String>) -> RecunsFlow<char, Vec<String>> {
+            if c == ' ' {
+                return RecunsFlow::None;
+            }
+            RecunsFlow::call_pooled::<Word>("word")
+        }
+        fn on_ret(
+            &mut self,
+            v: Box<dyn Any>,
+            data: &mut Vec<String>,
+        ) -> RecunsFlow<char, Vec<String>> {
+            data.push(*v.downcast::<String>().unwrap());
+            RecunsFlow::None
+        }
+        fn on_eof(&mut self, _: &mut Vec<String>) -> RecunsFlow<char, Vec<String>> {
+            RecunsFlow::End
+        }
+    }
+    struct Word(String);
+    impl Default for Word {
+        fn default() -> Self {
+            WORDS_MADE.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Self(String::new())
+        }
+    }
+    impl Recuns for Word {
+        type Input = char;
+        type Data = Vec<String>;
+
+        fn check(&mut self, &c: &char, _: &mut Vec<String>) -> RecunsFlow<char, Vec<String>> {
+            if c == ' ' {
+                return RecunsFlow::ret(std::mem::take(&mut self.0));
+            }
+            self.0.push(c);
+            RecunsFlow::None
+        }
+        fn on_eof(&mut self, _: &mut Vec<String>) -> RecunsFlow<char, Vec<String>> {
+            RecunsFlow::ret(std::mem::take(&mut self.0))
+        }
+        fn reset(&mut self) -> bool {
+            self.0.clear();
+            true
+        }
+    }
+
+    #[test]
+    fn test_pool() {
+        use std::sync::atomic::Ordering;
+        let mut p = Parser::new(vec![], Spaced, true).pool();
+        p.feed_all("ab cd e fgh".chars());
+        assert_eq!(p.finish().result.unwrap(), vec!["ab", "cd", "e", "fgh"]);
+        assert_eq!(WORDS_MADE.swap(0, Ordering::SeqCst), 1);
+
+        let mut p = Parser::new(vec![], Spaced, true);
+        p.feed_all("ab cd e fgh".chars());
+        assert_eq!(p.finish().result.unwrap(), vec!["ab", "cd", "e", "fgh"]);
+        assert_eq!(WORDS_MADE.swap(0, Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn test_driver() {
+        let mut code = "ac ab a".chars();
+        let mut steps = 0;
+        let r = Driver::new(vec![], Words)
+            .on_loop(|_| steps += 1)
+            .run(|_| code.next().map(Ok))
+            .result;
+        assert_eq!(r.unwrap().unwrap(), vec!["ac", "ab", "a"]);
+        assert!(steps > 7);
+
+        let mut code = "ac ab a".chars();
+        let mut left = 3;
+        let r = Driver::new(vec![], Words)
+            .cancel(move || {
+                left -= 1;
+                left == 0
+            })
+            .run(|_| code.next().map(Ok))
+            .result;
+        assert_eq!(r.unwrap(), None);
+
+        let mut code = "ab b ac".chars();
+        let mut errors = vec![];
+        let words: Vec<_> = Driver::new(vec![], Words)
+            .stop_when_err(false)
+            .iter(
+                &mut errors,
+                &mut vec![],
+                |_| code.next().map(Ok),
+                |d: &mut Vec<String>| Some(d.drain(..).collect()),
+            )
+            .collect();
+        assert_eq!(words, vec!["ab", "ac"]);
+        assert_eq!(errors.len(), 1);
+
+        let mut code = "ab b ac".chars();
+        let words: Vec<_> = Driver::new(vec![], Words)
+            .stop_when_err(false)
+            .try_iter(
+                |_| code.next().map(Ok),
+                |d: &mut Vec<String>| Some(d.drain(..).collect()),
+            )
+            .map(|r| r.map_err(|e| e.downcast_ref::<RecunsError>().cloned()))
+            .collect();
+        assert_eq!(
+            words,
+            vec![
+                Ok("ab".to_string()),
+                Err(Some(RecunsError::NoAlt(vec!["ab", "ac", "a"]))),
+                Ok("ac".to_string())
+            ]
+        );
+
+        let mut code = "ab b ac".chars();
+        let words: Vec<_> = Driver::new(vec![], Words)
+            .try_iter(
+                |_| code.next().map(Ok),
+                |d: &mut Vec<String>| Some(d.drain(..).collect()),
+            )
+            .collect();
+        assert_eq!(words.len(), 2);
+        assert!(words[1].is_err());
+
+        // What a failed alternative put in the data is rolled back before `yields` sees it
+        let root = |c: Option<&char>, _: &mut Vec<String>| match c {
+            Some(_) => RecunsFlow::alt("a", Scribe('a')).or("b", Scribe('b')),
+            None => RecunsFlow::End,
+        };
+        let mut code = "xy!;".chars();
+        let words: Vec<_> = Driver::new(vec![], root.recuns())
+            .iter(
+                &mut errors,
+                &mut vec![],
+                |_| code.next().map(Ok),
+                |d: &mut Vec<String>| Some(d.drain(..).collect()),
+            )
+            .collect();
+        assert_eq!(words, vec!["bx", "by", "b!"]);
+    }
+    /// Like `Echo` writing to the data
+    struct Scribe(char);
+    impl Recuns for Scribe {
+        type Input = char;
+        type Data = Vec<String>;
+
+        fn check(&mut self, &c: &char, data: &mut Vec<String>) -> RecunsFlow<char, Vec<String>> {
+            match c {
+                '!' if self.0 == 'a' => RecunsFlow::Fail,
+                ';' => RecunsFlow::End,
+                c => {
+                    data.push(format!("{}{}", self.0, c));
+                    RecunsFlow::None
+                }
+            }
+        }
+    }
+
+    /// Emits every char it reads, `a` fails on `!` so `b` is tried from the start of the alternative
+    struct Echo(char);
+    impl Recuns for Echo {
+        type Input = char;
+        type Data = ();
+
+        fn check(&mut self, &c: &char, _: &mut ()) -> RecunsFlow<char, ()> {
+            match c {
+                '!' if self.0 == 'a' => RecunsFlow::Fail,
+                ';' => RecunsFlow::End,
+                c => RecunsFlow::emit(format!("{}{}", self.0, c)),
+            }
+        }
+    }
+
+    #[test]
+    fn test_yield() {
+        let root = |c: Option<&char>, _: &mut ()| match c {
+            Some(_) => RecunsFlow::alt("a", Echo('a')).or("b", Echo('b')),
+            None => RecunsFlow::End,
+        };
+        let mut code = "xy;xy!;".chars();
+        let out: Vec<String> = Driver::new((), root.recuns())
+            .try_iter_yielded(|_| code.next().map(Ok))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(out, vec!["ax", "ay", "bx", "by", "b!"]);
+
+        // Emitting at the end of input without ending the frame leaves it unfinished
+        let root = |c: Option<&char>, _: &mut ()| match c {
+            Some(&c) => RecunsFlow::emit(c.to_string()),
+            None => RecunsFlow::emit("end".to_string()),
+        };
+        let mut code = "ab".chars();
+        let out = Driver::new((), root.recuns())
+            .try_iter_yielded::<String>(|_| code.next().map(Ok))
+            .map(|r| r.map_err(|e| e.downcast_ref::<RecunsError>().cloned()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            out,
+            vec![
+                Ok("a".to_string()),
+                Ok("b".to_string()),
+                Err(Some(RecunsError::UnfinishedAtEof))
+            ]
+        );
+
+        // A value of another type than the driver's output stops the run
+        let root = |c: Option<&char>, _: &mut ()| match c {
+            Some('b') => RecunsFlow::emit(1),
+            Some(&c) => RecunsFlow::emit(c.to_string()),
+            None => RecunsFlow::End,
+        };
+        let mut code = "abc".chars();
+        let out = Driver::new((), root.recuns())
+            .try_iter_yielded::<String>(|_| code.next().map(Ok))
+            .collect::<Vec<_>>();
+        assert_eq!(out.len(), 2);
+        assert_eq!(out[0].as_ref().unwrap(), "a");
+        let e = out[1].as_ref().unwrap_err();
+        assert_eq!(
+            e.downcast_ref::<RecunsError>(),
+            Some(&RecunsError::YieldType(std::any::type_name::<String>()))
+        );
+        assert_eq!((e.code, e.index), (Some("R0010"), 1));
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum DigitError {
+        Recuns(RecunsError),
+        NotDigit(char),
+    }
+    impl From<RecunsError> for DigitError {
+        fn from(e: RecunsError) -> Self {
+            Self::Recuns(e)
+        }
+    }
+    struct Digits;
+    impl Recuns<DigitError> for Digits {
+        type Input = char;
+        type Data = u32;
+
+        fn check(&mut self, &c: &char, n: &mut u32) -> RecunsFlow<char, u32, DigitError> {
+            match c.to_digit(10) {
+                Some(d) => {
+                    *n = *n * 10 + d;
+                    RecunsFlow::None
+                }
+                None => RecunsFlow::Err(DigitError::NotDigit(c)),
+            }
+        }
+        fn on_eof(&mut self, _: &mut u32) -> RecunsFlow<char, u32, DigitError> {
+            RecunsFlow::End
+        }
+    }
+
+    #[test]
+    fn test_typed_error() {
+        let mut code = "123".chars();
+        let r = Driver::new(0, Digits).run(|_| code.next().map(Ok)).result;
+        assert_eq!(r.unwrap(), Some(123));
+
+        let mut code = "1x3".chars();
+        let e = Driver::new(0, Digits)
+            .run(|_| code.next().map(Ok))
+            .result
+            .unwrap_err();
+        assert_eq!(e[0].error, DigitError::NotDigit('x'));
+        assert_eq!(e[0].index, 1);
+
+        let mut code = "123".chars();
+        let e = Driver::new(0, Digits)
+            .fuel(2)
+            .run(|_| code.next().map(Ok))
+            .result
+            .unwrap_err();
+        assert_eq!(e[0].error, DigitError::Recuns(RecunsError::OutOfFuel));
+
+        // Closure frames carry the typed error too
+        let root = |c: Option<&char>, _: &mut u32| -> RecunsFlow<char, u32, DigitError> {
+            if c.is_none() {
+                return RecunsFlow::End;
+            }
+            (|c: Option<&char>, n: &mut u32| match c {
+                Some(&c) => match c.to_digit(10) {
+                    Some(d) => {
+                        *n = *n * 10 + d;
+                        RecunsFlow::None
+                    }
+                    None => DigitError::NotDigit(c).into(),
+                },
+                None => RecunsFlow::End,
+            })
+            .rfcall("digits")
+        };
+        let mut code = "12".chars();
+        let r = Driver::new(0, root.recuns())
+            .run(|_| code.next().map(Ok))
+            .result;
+        assert_eq!(r.unwrap(), Some(12));
+
+        let mut code = "1x".chars();
+        let e = Driver::new(0, root.recuns())
+            .run(|_| code.next().map(Ok))
+            .result
+            .unwrap_err();
+        assert_eq!(e[0].error, DigitError::NotDigit('x'));
+        assert_eq!(e[0].stack, vec!["root", "digits"]);
+    }
+
+    /// Not `Clone`, so inputs can only be lent to frames and moved for replay
+    struct Num(u32);
+    type NumFlow = RecunsFlow<Num, Vec<u32>>;
+    struct Sums;
+    impl Recuns for Sums {
+        type Input = Num;
+        type Data = Vec<u32>;
+
+        fn check(&mut self, _: &Num, _: &mut Vec<u32>) -> NumFlow {
+            RecunsFlow::alt("pair", Pair(None)).or("one", One)
+        }
+        fn on_ret(&mut self, v: Box<dyn Any>, data: &mut Vec<u32>) -> NumFlow {
+            data.push(*v.downcast::<u32>().unwrap());
+            RecunsFlow::None
+        }
+        fn on_eof(&mut self, _: &mut Vec<u32>) -> NumFlow {
+            RecunsFlow::End
+        }
+    }
+    /// Sums two inputs if the second is even
+    struct Pair(Option<u32>);
+    impl Recuns for Pair {
+        type Input = Num;
+        type Data = Vec<u32>;
+
+        fn check(&mut self, n: &Num, _: &mut Vec<u32>) -> NumFlow {
+            match self.0 {
+                None => {
+                    self.0 = Some(n.0);
+                    RecunsFlow::None
+                }
+                Some(a) if n.0.is_multiple_of(2) => RecunsFlow::ret(a + n.0),
+                Some(_) => RecunsFlow::Fail,
+            }
+        }
+        fn on_eof(&mut self, _: &mut Vec<u32>) -> NumFlow {
+            RecunsFlow::Fail
+        }
+    }
+    struct One;
+    impl Recuns for One {
+        type Input = Num;
+        type Data = Vec<u32>;
+
+        fn check(&mut self, n: &Num, _: &mut Vec<u32>) -> NumFlow {
+            RecunsFlow::ret(n.0)
+        }
+    }
+
+    #[test]
+    fn test_no_clone() {
+        let mut nums = vec![1, 2, 3, 5].into_iter().map(Num);
+        let r = do_loop(vec![], Sums, true, |_| nums.next().map(Ok))
+            .result
+            .unwrap();
+        assert_eq!(r.unwrap(), vec![3, 3, 5]);
+    }
+
+    /// Frames kept inline on the stack
+    enum Lex {
+        Root,
+        Word(String),
+        Space,
+    }
+    type LexFlow = RecunsFlow<char, Vec<String>, Arc<Error>, Lex>;
+    impl Frames for Lex {
+        type Input = char;
+        type Data = Vec<String>;
+        type Error = Arc<Error>;
+
+        fn check(&mut self, &c: &char, _: &mut Vec<String>) -> LexFlow {
+            match self {
+                Lex::Root if c.is_whitespace() => RecunsFlow::Call(Lex::Space, "space"),
+                Lex::Root => RecunsFlow::Call(Lex::Word(String::new()), "word"),
+                Lex::Word(w) if c.is_alphanumeric() => {
+                    w.push(c);
+                    RecunsFlow::None
+                }
+                Lex::Word(w) => RecunsFlow::ret_redo(std::mem::take(w)),
+                Lex::Space if c.is_whitespace() => RecunsFlow::None,
+                Lex::Space => RecunsFlow::EndReDo,
+            }
+        }
+        fn on_eof(&mut self, _: &mut Vec<String>) -> LexFlow {
+            match self {
+                Lex::Word(w) => RecunsFlow::ret(std::mem::take(w)),
+                _ => RecunsFlow::End,
+            }
+        }
+        fn on_ret(&mut self, v: Box<dyn Any>, d: &mut Vec<String>) -> LexFlow {
+            d.push(*v.downcast::<String>().unwrap());
+            RecunsFlow::None
+        }
+    }
+
+    #[test]
+    fn test_frames() {
+        let mut p = Parser::with_frames(vec![], Lex::Root, true);
+        p.feed_all("ab  cd e".chars());
+        assert_eq!(p.finish().result.unwrap(), vec!["ab", "cd", "e"]);
+    }
+
+    struct Ops;
+    impl Recuns for Ops {
+        type Input = char;
+        type Data = Vec<String>;
+
+        fn check(&mut self, &c: &char, d: &mut Vec<String>) -> RecunsFlow<char, Vec<String>> {
+            match c {
+                '-' | '>' => RecunsFlow::Peek(2),
+                ' ' => RecunsFlow::None,
+                c => {
+                    d.push(c.to_string());
+                    RecunsFlow::None
+                }
+            }
+        }
+        fn on_peek(
+            &mut self,
+            &c: &char,
+            ahead: &mut Lookahead<char>,
+            d: &mut Vec<String>,
+        ) -> RecunsFlow<char, Vec<String>> {
+            let op = match (c, ahead.get(0), ahead.get(1)) {
+                ('-', Some('>'), _) => "->",
+                ('>', Some('>'), Some('=')) => ">>=",
+                ('>', Some('>'), _) => ">>",
+                _ => "",
+            };
+            if op.is_empty() {
+                d.push(c.to_string());
+            } else {
+                ahead.consume(op.len() - 1);
+                d.push(op.to_string());
+            }
+            RecunsFlow::None
+        }
+        fn on_eof(&mut self, _: &mut Vec<String>) -> RecunsFlow<char, Vec<String>> {
+            RecunsFlow::End
+        }
+    }
+
+    #[test]
+    fn test_peek() {
+        let code = "->>>= a>> - >";
+        let ops = vec!["->", ">>=", "a", ">>", "-", ">"];
+        let mut chars = code.chars();
+        let r = do_loop(vec![], Ops, true, |_| chars.next().map(Ok))
+            .result
+            .unwrap();
+        assert_eq!(r.unwrap(), ops);
+
+        let mut p = Parser::new(vec![], Ops, true);
+        for c in code.chars() {
+            assert!(p.feed(c));
+        }
+        assert_eq!(p.finish().result.unwrap(), ops);
+
+        // Peeking again from `on_peek` at what is already read ahead can't go on forever
+        struct Stare;
+        impl Recuns for Stare {
+            type Input = char;
+            type Data = ();
+
+            fn check(&mut self, _: &char, _: &mut ()) -> RecunsFlow<char, ()> {
+                RecunsFlow::Peek(1)
+            }
+            fn on_peek(
+                &mut self,
+                _: &char,
+                _: &mut Lookahead<char>,
+                _: &mut (),
+            ) -> RecunsFlow<char, ()> {
+                RecunsFlow::Peek(1)
+            }
+        }
+        let mut p = Parser::new((), Stare, true);
+        assert!(!p.feed_all("ab".chars()));
+        let e = p.finish().result.unwrap_err();
+        assert_eq!(
+            e[0].downcast_ref::<RecunsError>(),
+            Some(&RecunsError::Spin(vec!["root"]))
+        );
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_peek_async() {
+        use futures::executor::block_on;
+        use futures::{Stream, StreamExt};
+        use std::pin::Pin;
+        use std::task::{Context, Poll};
+
+        /// Only ready every other poll, like input arriving in packets
+        struct Trickle(std::vec::IntoIter<char>, bool);
+        impl Stream for Trickle {
+            type Item = RecunsResult<char>;
+
+            fn poll_next(
+                mut self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+            ) -> Poll<Option<Self::Item>> {
+                self.1 = !self.1;
+                if self.1 {
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+                Poll::Ready(self.0.next().map(Ok))
+            }
+        }
+
+        let code = "->>>= a>> - >".chars().collect::<Vec<_>>();
+        let mut errors = vec![];
+        let mut warnings = vec![];
+        let r = do_iter_async(
+            vec![],
+            Ops,
+            true,
+            &mut errors,
+            &mut warnings,
+            Trickle(code.into_iter(), false),
+            |d: &mut Vec<String>| Some(d.drain(..).collect()),
+        );
+        let r = block_on(r.collect::<Vec<_>>());
+        assert!(errors.is_empty());
+        assert_eq!(r, vec!["->", ">>=", "a", ">>", "-", ">"]);
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let root = RecunsCloneBox(
+            (|c: Option<&char>, d: &mut Vec<char>| match c {
+                Some(&c) => {
+                    d.push(c);
+                    RecunsFlow::None
+                }
+                None => RecunsFlow::End,
+            })
+            .recuns(),
+        );
+        let mut p: Parser<_, _> = Parser::new(vec![], root, true);
+        p.feed('a');
+        let cp = p.state().checkpoint().unwrap();
+        p.feed_all("bc".chars());
+        assert_eq!(p.data(), &vec!['a', 'b', 'c']);
+        p.state_mut().restore(cp);
+        p.feed('d');
+        assert_eq!(p.finish().result.unwrap(), vec!['a', 'd']);
+
+        let p = Parser::new(vec![], Words, true);
+        assert!(p.state().checkpoint().is_none());
+    }
+
+    #[test]
+    fn test_eof() {
+        let root = (|c: Option<&char>, _: &mut Vec<char>| {
+            if c.is_none() {
+                return RecunsFlow::End;
+            }
+            (|c: Option<&char>, d: &mut Vec<char>| match c {
+                Some(&c) => {
+                    d.push(c);
+                    RecunsFlow::None
+                }
+                None => {
+                    d.push('$');
+                    RecunsFlow::End
+                }
+            })
+            .rfcall("child")
+        })
+        .recuns();
+        let mut p: Parser<_, _> = Parser::new(vec![], root, true);
+        p.feed_all("ab".chars());
+        assert_eq!(p.state().stack_names(), &["root", "child"]);
+        assert_eq!(p.finish().result.unwrap(), vec!['a', 'b', '$']);
+
+        let root = (|_: Option<&char>, _: &mut ()| RecunsFlow::None).recuns();
+        let mut code = "a".chars();
+        let e = do_loop((), root, true, |_| code.next().map(Ok))
+            .result
+            .unwrap_err();
+        assert!(e[0].eof);
+        assert_eq!(
+            e[0].downcast_ref::<RecunsError>(),
+            Some(&RecunsError::UnfinishedAtEof)
+        );
+    }
+
+    #[test]
+    fn test_fuel() {
+        let root = (|_: Option<&char>, _: &mut ()| {
+            (|_: Option<&char>, _: &mut ()| RecunsFlow::EndReDo).rfcall("spin")
+        })
+        .recuns();
+        let mut p = Parser::new((), root, false).max_redo(None).fuel(1000);
+        assert!(!p.feed('a'));
+        assert_eq!(p.state().fuel, Some(0));
+        let e = p.finish().result.unwrap_err();
+        assert_eq!(e.len(), 1);
+        assert_eq!(
+            e[0].downcast_ref::<RecunsError>(),
+            Some(&RecunsError::OutOfFuel)
+        );
+    }
+
+    #[test]
+    fn test_spin() {
+        let root = (|_: Option<&char>, _: &mut ()| {
+            (|_: Option<&char>, _: &mut ()| RecunsFlow::EndReDo).rfcall("spin")
+        })
+        .recuns();
+        let mut p = Parser::new((), root, false).max_redo(Some(16));
+        assert!(!p.feed('a'));
+        let e = p.finish().result.unwrap_err();
+        assert_eq!(e.len(), 1);
+        assert_eq!(
+            e[0].downcast_ref::<RecunsError>(),
+            Some(&RecunsError::Spin(vec!["spin", "root"]))
+        );
+        assert_eq!(e[0].index, 0);
+    }
+
+    #[test]
+    fn test_render() {
+        let src = "let a = (1,\n  2,\n  3,\n  4;\n";
+        let d = Diagnostic::error("unclosed paren")
+            .code("E0001")
+            .span(25..26)
+            .label(8..9, "opened here")
+            .note("parens must match");
+        assert_eq!(
+            d.render(src),
+            "\
+error[E0001]: unclosed paren
+ --> 4:4
+  |
+1 | let a = (1,
+  |         - opened here
+...
+4 |   4;
+  |    ^
+  = note: parens must match
+"
+        );
+
+        let src = "é = ?";
+        let d = Diagnostic::error("bad").span(5..6);
+        assert_eq!(
+            Snippet::new(src).bytes().render(&d),
+            "error: bad\n --> 1:5\n  |\n1 | é = ?\n  |     ^\n"
+        );
+    }
 }
@@ -0,0 +1,172 @@
+use crate::*;
+use anyhow::Error;
+use std::sync::Arc;
+
+/// Push-based driver, feed inputs as they arrive and call `finish` at the end of input
+pub struct Parser<'a, I, D = (), E = Arc<Error>, F = RecunsFrame<'a, I, D, E>> {
+    pub(crate) state: State<'a, I, D, E, F>,
+    pub(crate) eof: bool,
+    pub(crate) stopped: bool,
+}
+impl<'a, I: 'a, D, E: From<RecunsError> + 'a> Parser<'a, I, D, E> {
+    #[inline]
+    pub fn new(
+        data: D,
+        root: impl Recuns<E, Data = D, Input = I> + 'a,
+        stop_when_err: bool,
+    ) -> Self {
+        Self::with_frames(data, Box::new(root), stop_when_err)
+    }
+}
+impl<'a, I: 'a, D, E: From<RecunsError>, F: Frames<Input = I, Data = D, Error = E>>
+    Parser<'a, I, D, E, F>
+{
+    /// Start from a root frame of any `Frames` type, such as an enum of frames
+    #[inline]
+    pub fn with_frames(data: D, root: F, stop_when_err: bool) -> Self {
+        let mut state = State::new(stop_when_err, data);
+        state.push(root, "root");
+        Self {
+            state,
+            eof: false,
+            stopped: false,
+        }
+    }
+
+    /// Refuse to push frames past this depth
+    #[inline]
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.state.max_depth = Some(depth);
+        self
+    }
+    /// Reuse frames pushed with `RecunsFlow::CallPooled`, see `State::set_pool`
+    #[inline]
+    pub fn pool(mut self) -> Self {
+        self.state.set_pool(true);
+        self
+    }
+    /// Keep going after errors by unwinding to frames that `sync`, see `State::recover`
+    #[inline]
+    pub fn recover(mut self) -> Self {
+        self.state.recover = true;
+        self
+    }
+    /// Abort after this many steps
+    #[inline]
+    pub fn fuel(mut self, fuel: usize) -> Self {
+        self.state.fuel = Some(fuel);
+        self
+    }
+    /// Times an input can be redone without progress, `None` to never check
+    #[inline]
+    pub fn max_redo(mut self, max: Option<usize>) -> Self {
+        self.state.max_redo = max;
+        self
+    }
+
+    #[inline]
+    pub fn state(&self) -> &State<'a, I, D, E, F> {
+        &self.state
+    }
+    #[inline]
+    pub fn state_mut(&mut self) -> &mut State<'a, I, D, E, F> {
+        &mut self.state
+    }
+    #[inline]
+    pub fn data(&self) -> &D {
+        &self.state.data
+    }
+    #[inline]
+    pub fn data_mut(&mut self) -> &mut D {
+        &mut self.state.data
+    }
+
+    /// No more input will be accepted, the end was reached, the stack is empty or an error stopped it
+    #[inline]
+    pub fn is_finished(&self) -> bool {
+        self.eof || self.stopped
+    }
+
+    /// Returns `false` if the input was not accepted because the parser is finished
+    pub fn feed(&mut self, input: I) -> bool {
+        if self.is_finished() {
+            return false;
+        }
+        self.dispatch(input);
+        while self.redo() {}
+        !self.stopped
+    }
+    pub fn feed_all(&mut self, inputs: impl IntoIterator<Item = I>) -> bool {
+        for input in inputs {
+            if !self.feed(input) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Signal the end of input and take the data back with the warnings
+    pub fn finish(mut self) -> Outcome<D, E> {
+        if !self.is_finished() {
+            self.dispatch_eof();
+        }
+        while self.redo() {}
+        let warnings = self.state.settled_warnings();
+        let result = if self.state.errors.is_empty() {
+            Ok(self.state.data)
+        } else {
+            Err(self.state.errors)
+        };
+        Outcome { result, warnings }
+    }
+
+    #[inline]
+    pub(crate) fn dispatch(&mut self, input: I) {
+        self.dispatch_input(Some(input))
+    }
+    #[inline]
+    pub(crate) fn dispatch_eof(&mut self) {
+        self.eof = true;
+        self.dispatch_input(None)
+    }
+    #[inline]
+    pub(crate) fn dispatch_input(&mut self, input: Option<I>) {
+        self.state.read += 1;
+        if crate::state::input(&mut self.state, input).is_none() {
+            self.stopped = true;
+        }
+    }
+    /// Run one pending redo, returns `false` if there was nothing to run
+    #[inline]
+    pub(crate) fn redo(&mut self) -> bool {
+        if self.stopped {
+            return false;
+        }
+        match redo(&mut self.state) {
+            Some(r) => {
+                if r.is_none() {
+                    self.stopped = true;
+                }
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// What a finished parse gives back, the warnings come whether it succeeded or not
+#[derive(Debug)]
+pub struct Outcome<T, E = Arc<Error>> {
+    pub result: RecunsResultErrs<T, E>,
+    /// See `RecunsFlow::Warn`
+    pub warnings: Vec<Diagnostic<E>>,
+}
+impl<T, E> Outcome<T, E> {
+    #[inline]
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Outcome<U, E> {
+        Outcome {
+            result: self.result.map(f),
+            warnings: self.warnings,
+        }
+    }
+}
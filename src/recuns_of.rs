@@ -1,7 +1,9 @@
 use crate::*;
+use anyhow::Error;
+use std::sync::Arc;
 
-pub trait RecunsOfFn<Input, Data> {
-    type OutPut: Recuns<Input = Input, Data = Data>;
+pub trait RecunsOfFn<Input, Data, E: From<RecunsError> = Arc<Error>> {
+    type OutPut: Recuns<E, Input = Input, Data = Data>;
     fn recuns(self) -> Self::OutPut;
 }
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -9,9 +11,9 @@ pub struct RecunsFnBox<F, Input, Data> {
     f: F,
     _i: std::marker::PhantomData<(Input, Data)>,
 }
-impl<F, Input, Data> RecunsFnBox<F, Input, Data>
+impl<F, Input, Data, E> RecunsFnBox<F, Input, Data>
 where
-    F: FnMut(Input, &mut Data, bool) -> RecunsFlow<Input, Data>,
+    F: FnMut(Option<&Input>, &mut Data) -> RecunsFlow<Input, Data, E>,
 {
     #[inline]
     pub fn new(f: F) -> Self {
@@ -21,9 +23,9 @@ where
         }
     }
 }
-impl<F, Input, Data> Recuns for RecunsFnBox<F, Input, Data>
+impl<F, Input, Data, E: From<RecunsError>> Recuns<E> for RecunsFnBox<F, Input, Data>
 where
-    F: FnMut(Input, &mut Data, bool) -> RecunsFlow<Input, Data>,
+    F: FnMut(Option<&Input>, &mut Data) -> RecunsFlow<Input, Data, E>,
 {
     type Input = Input;
     type Data = Data;
@@ -31,16 +33,19 @@ where
     #[inline]
     fn check(
         &mut self,
-        input: Self::Input,
+        input: &Self::Input,
         data: &mut Self::Data,
-        eof: bool,
-    ) -> RecunsFlow<Self::Input, Self::Data> {
-        (self.f)(input, data, eof)
+    ) -> RecunsFlow<Self::Input, Self::Data, E> {
+        (self.f)(Some(input), data)
+    }
+    #[inline]
+    fn on_eof(&mut self, data: &mut Self::Data) -> RecunsFlow<Self::Input, Self::Data, E> {
+        (self.f)(None, data)
     }
 }
-impl<Input, F, Data> RecunsOfFn<Input, Data> for F
+impl<Input, F, Data, E: From<RecunsError>> RecunsOfFn<Input, Data, E> for F
 where
-    F: FnMut(Input, &mut Data, bool) -> RecunsFlow<Input, Data>,
+    F: FnMut(Option<&Input>, &mut Data) -> RecunsFlow<Input, Data, E>,
 {
     type OutPut = RecunsFnBox<F, Input, Data>;
 
@@ -49,3 +54,56 @@ where
         RecunsFnBox::new(self)
     }
 }
+
+/// Wraps a clonable frame so it can `fork` for `State::checkpoint`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RecunsCloneBox<R>(pub R);
+impl<R, E: From<RecunsError>> Recuns<E> for RecunsCloneBox<R>
+where
+    R: Recuns<E> + Clone + 'static,
+{
+    type Input = R::Input;
+    type Data = R::Data;
+
+    #[inline]
+    fn check(
+        &mut self,
+        input: &Self::Input,
+        data: &mut Self::Data,
+    ) -> RecunsFlow<Self::Input, Self::Data, E> {
+        self.0.check(input, data)
+    }
+    #[inline]
+    fn on_eof(&mut self, data: &mut Self::Data) -> RecunsFlow<Self::Input, Self::Data, E> {
+        self.0.on_eof(data)
+    }
+    #[inline]
+    fn on_ret(
+        &mut self,
+        value: Box<dyn std::any::Any>,
+        data: &mut Self::Data,
+    ) -> RecunsFlow<Self::Input, Self::Data, E> {
+        self.0.on_ret(value, data)
+    }
+    #[inline]
+    fn on_peek(
+        &mut self,
+        input: &Self::Input,
+        ahead: &mut Lookahead<Self::Input>,
+        data: &mut Self::Data,
+    ) -> RecunsFlow<Self::Input, Self::Data, E> {
+        self.0.on_peek(input, ahead, data)
+    }
+    #[inline]
+    fn sync(&mut self, input: &Self::Input, data: &mut Self::Data) -> bool {
+        self.0.sync(input, data)
+    }
+    #[inline]
+    fn reset(&mut self) -> bool {
+        self.0.reset()
+    }
+    #[inline]
+    fn fork(&self) -> Option<RecunsBox<Self::Input, Self::Data, E>> {
+        Some(Box::new(self.clone()))
+    }
+}
@@ -0,0 +1,191 @@
+use crate::*;
+use std::fmt::{Display, Write};
+use std::ops::Range;
+
+/// Renders diagnostics under the source lines they point at, in the style of rustc
+///
+/// Spans count chars of the source, or bytes with `bytes`
+#[derive(Debug, Clone, Copy)]
+pub struct Snippet<'s> {
+    source: &'s str,
+    name: Option<&'s str>,
+    bytes: bool,
+}
+impl<'s> Snippet<'s> {
+    #[inline]
+    pub fn new(source: &'s str) -> Self {
+        Self {
+            source,
+            name: None,
+            bytes: false,
+        }
+    }
+    /// Shown before the line and column, like a file name
+    #[inline]
+    pub fn name(mut self, name: &'s str) -> Self {
+        self.name = Some(name);
+        self
+    }
+    /// Spans are byte offsets into the source
+    #[inline]
+    pub fn bytes(mut self) -> Self {
+        self.bytes = true;
+        self
+    }
+
+    /// A diagnostic without a span points at the end of the source when it happened at EOF,
+    /// otherwise at `Diagnostic::index`
+    pub fn render<E: Display>(&self, d: &Diagnostic<E>) -> String {
+        let lines = Lines::new(self.source);
+        let primary = match &d.span {
+            Some(span) => self.chars(span),
+            None if d.eof => lines.len..lines.len,
+            None => d.primary_span(),
+        };
+        let mut marks = vec![Mark {
+            span: primary.clone(),
+            primary: true,
+            message: None,
+        }];
+        marks.extend(d.labels.iter().map(|l| Mark {
+            span: self.chars(&l.span),
+            primary: false,
+            message: Some(&l.message),
+        }));
+
+        let mut rows: Vec<usize> = vec![];
+        for m in &marks {
+            let (first, _) = lines.locate(m.span.start);
+            let (last, _) = lines.locate(m.last());
+            rows.extend(first..=last);
+        }
+        rows.sort_unstable();
+        rows.dedup();
+        let width = (rows.last().copied().unwrap_or(0) + 1).to_string().len();
+        let pad = " ".repeat(width);
+
+        let mut out = String::new();
+        match d.code {
+            Some(code) => writeln!(out, "{}[{}]: {}", d.severity, code, d.error),
+            None => writeln!(out, "{}: {}", d.severity, d.error),
+        }
+        .unwrap();
+        let (row, col) = lines.locate(primary.start);
+        match self.name {
+            Some(name) => writeln!(out, "{}--> {}:{}:{}", pad, name, row + 1, col + 1),
+            None => writeln!(out, "{}--> {}:{}", pad, row + 1, col + 1),
+        }
+        .unwrap();
+        writeln!(out, "{} |", pad).unwrap();
+        let mut prev: Option<usize> = None;
+        for &row in &rows {
+            if matches!(prev, Some(p) if row > p + 1) {
+                writeln!(out, "...").unwrap();
+            }
+            prev = Some(row);
+            let text = lines.text(row);
+            writeln!(out, "{:>w$} | {}", row + 1, text, w = width).unwrap();
+            for m in &marks {
+                let (first, start) = lines.locate(m.span.start);
+                let (last, end) = lines.locate(m.last());
+                if row < first || row > last {
+                    continue;
+                }
+                let from = if row == first { start } else { 0 };
+                let to = if row == last {
+                    end + usize::from(!m.span.is_empty())
+                } else {
+                    text.chars().count()
+                };
+                let c = if m.primary { "^" } else { "-" };
+                let mut line = format!(
+                    "{} | {}{}",
+                    pad,
+                    " ".repeat(from),
+                    c.repeat(to.saturating_sub(from).max(1))
+                );
+                if let (true, Some(msg)) = (row == last, m.message) {
+                    write!(line, " {}", msg).unwrap();
+                }
+                writeln!(out, "{}", line).unwrap();
+            }
+        }
+        for note in &d.notes {
+            writeln!(out, "{} = note: {}", pad, note).unwrap();
+        }
+        out
+    }
+
+    /// Turn a span into char offsets
+    fn chars(&self, span: &Range<usize>) -> Range<usize> {
+        if !self.bytes {
+            return span.clone();
+        }
+        let at = |b: usize| {
+            self.source
+                .char_indices()
+                .take_while(|(i, _)| *i < b)
+                .count()
+        };
+        at(span.start)..at(span.end)
+    }
+}
+
+impl<E: Display> Diagnostic<E> {
+    /// Render over a source whose chars the spans count, see `Snippet`
+    #[inline]
+    pub fn render(&self, source: &str) -> String {
+        Snippet::new(source).render(self)
+    }
+}
+
+struct Mark<'d> {
+    span: Range<usize>,
+    primary: bool,
+    message: Option<&'d str>,
+}
+impl Mark<'_> {
+    /// The offset of the last char marked, the start for an empty span
+    #[inline]
+    fn last(&self) -> usize {
+        if self.span.is_empty() {
+            self.span.start
+        } else {
+            self.span.end - 1
+        }
+    }
+}
+
+/// The source split into lines, with the char offset each starts at
+struct Lines<'s> {
+    lines: Vec<(usize, &'s str)>,
+    len: usize,
+}
+impl<'s> Lines<'s> {
+    fn new(source: &'s str) -> Self {
+        let mut lines = vec![];
+        let mut start = 0;
+        for line in source.split('\n') {
+            let n = line.chars().count();
+            lines.push((start, line.strip_suffix('\r').unwrap_or(line)));
+            start += n + 1;
+        }
+        Self {
+            lines,
+            len: source.chars().count(),
+        }
+    }
+    /// The row and column of a char offset, offsets past the end are on the last line
+    fn locate(&self, offset: usize) -> (usize, usize) {
+        let row = self
+            .lines
+            .iter()
+            .rposition(|(start, _)| *start <= offset)
+            .unwrap_or(0);
+        (row, offset - self.lines[row].0)
+    }
+    #[inline]
+    fn text(&self, row: usize) -> &'s str {
+        self.lines[row].1
+    }
+}
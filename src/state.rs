@@ -1,503 +1,1029 @@
 use crate::*;
 use anyhow::Error;
-use std::collections::VecDeque;
+#[cfg(feature = "async")]
+use futures_core::Stream;
+use std::any::{Any, TypeId};
+use std::collections::{HashMap, VecDeque};
 use std::fmt;
 use std::fmt::{Debug, Formatter};
+use std::marker::PhantomData;
+use std::ops::Range;
 use std::sync::*;
 
-pub struct State<'a, 'b, I, D = ()> {
+type AltIter<F> = <RecunsAlts<F> as IntoIterator>::IntoIter;
+
+/// Default for `State::max_redo`
+pub const DEFAULT_MAX_REDO: usize = 1024;
+
+pub struct State<'a, I, D = (), E = Arc<Error>, F = RecunsFrame<'a, I, D, E>> {
     pub stop_when_err: bool,
+    /// After an error unwind to the nearest frame that `sync`s on an input instead of stopping,
+    /// skipping the inputs no frame syncs on
+    pub recover: bool,
+    /// Frames can't be pushed past this depth
+    pub max_depth: Option<usize>,
+    /// Steps left before aborting, each `check`, `on_peek` and redo costs one
+    pub fuel: Option<usize>,
+    /// Times an input can be redone without the stack growing or shrinking past what it already was
+    pub max_redo: Option<usize>,
 
     pub data: D,
 
-    pub states: Vec<Box<dyn 'a + Recuns<Input = I, Data = D>>>,
-    pub queue: Vec<Box<dyn 'a + FnMut(&mut Self)>>,
-    pub errors: &'b mut Vec<Arc<Error>>,
+    pub states: Vec<F>,
+    /// The name of each frame in `states`
+    pub(crate) names: Vec<&'static str>,
+    /// The type of each frame in `states` that was pushed through the pool
+    pub(crate) pooled: Vec<Option<TypeId>>,
+    /// Reset frames waiting to be reused, by type, `None` unless pooling is on
+    pub(crate) pool: Option<HashMap<TypeId, Vec<F>>>,
+    /// Values emitted with `RecunsFlow::Yield`
+    yielded: VecDeque<Box<dyn Any>>,
+    /// The current input has to be checked again
+    pub(crate) pending: bool,
+    /// An error happened and no frame synced since, `Some(true)` if it was at the end of input
+    pub(crate) recovering: Option<bool>,
+    /// Stack depth and input index of the last error while recovering
+    pub(crate) errored: Option<(usize, usize)>,
+    pub errors: Vec<Diagnostic<E>>,
+    /// Diagnostics that don't stop the parse, see `RecunsFlow::Warn`
+    pub warnings: Vec<Diagnostic<E>>,
+
+    /// How many inputs were taken from the source, the end of input counts as one
+    pub(crate) read: usize,
+    /// Index of the input being checked
+    pub(crate) index: usize,
+    pub(crate) at_eof: bool,
+
+    pub(crate) alts: Vec<AltPoint<D, F>>,
+    /// Inputs seen since the oldest pending alternative, `None` is the end of input
+    pub(crate) log: Vec<Option<I>>,
+    /// Inputs waiting to be dispatched, read ahead for a peek or replayed after a rewind
+    pub(crate) ahead: VecDeque<Option<I>>,
+    /// An input with its index, waiting for `RecunsFlow::Peek` to be satisfied
+    pub(crate) peek: Option<(I, usize, usize)>,
+    /// The input being checked, `Some(None)` at the end of input, taken once a frame consumed it
+    pub(crate) current: Option<Option<I>>,
+    /// Inputs consumed by `on_peek`, logged after the current input
+    pub(crate) eaten: Vec<I>,
+    pub(crate) spin: Spin,
+    /// What was expected at the furthest input a frame failed on
+    pub(crate) expected: Option<Expected>,
+    _frames: PhantomData<&'a ()>,
 }
-impl<'a, 'b, I> State<'a, 'b, I, ()> {
+impl<'a, I> State<'a, I, ()> {
     #[inline]
-    pub fn new_no_data(stop_when_err: bool, errors: &'b mut Vec<Arc<Error>>) -> Self {
+    pub fn new_no_data(stop_when_err: bool) -> Self {
+        Self::new(stop_when_err, ())
+    }
+}
+impl<'a, I, D, E, F> State<'a, I, D, E, F> {
+    #[inline]
+    pub fn new(stop_when_err: bool, data: D) -> Self {
         Self {
             stop_when_err,
+            recover: false,
+            max_depth: None,
+            fuel: None,
+            max_redo: Some(DEFAULT_MAX_REDO),
 
-            data: (),
+            data,
 
             states: vec![],
-            queue: vec![],
-            errors,
+            names: vec![],
+            pooled: vec![],
+            pool: None,
+            yielded: VecDeque::new(),
+            pending: false,
+            recovering: None,
+            errored: None,
+            errors: vec![],
+            warnings: vec![],
+
+            read: 0,
+            index: 0,
+            at_eof: false,
+
+            alts: vec![],
+            log: vec![],
+            ahead: VecDeque::new(),
+            peek: None,
+            current: None,
+            eaten: vec![],
+            spin: Spin::default(),
+            expected: None,
+            _frames: PhantomData,
+        }
+    }
+    #[inline]
+    pub fn push(&mut self, rec: F, name: &'static str) {
+        self.states.push(rec);
+        self.names.push(name);
+        self.pooled.push(None);
+    }
+    /// # Safety
+    /// Popping a frame the driver is still dispatching to breaks the flow, only pop between calls
+    #[inline]
+    pub unsafe fn pop(&mut self) -> Option<F> {
+        self.names.pop();
+        self.pooled.pop();
+        self.states.pop()
+    }
+    /// Keep ended frames pushed with `RecunsFlow::CallPooled` to reuse them for the next ones of their type
+    #[inline]
+    pub fn set_pool(&mut self, on: bool) {
+        self.pool = if on { Some(HashMap::new()) } else { None };
+    }
+    /// Index of the input being checked, counted from 0
+    #[inline]
+    pub fn index(&self) -> usize {
+        self.index
+    }
+    #[inline]
+    pub(crate) fn diagnostic(&self, error: E) -> Diagnostic<E> {
+        self.locate(Diagnostic::error(error))
+    }
+    /// Fill in where the driver is in a diagnostic returned by a frame
+    #[inline]
+    pub(crate) fn locate(&self, mut d: Diagnostic<E>) -> Diagnostic<E> {
+        d.stack = self.names.clone();
+        d.index = self.index;
+        d.eof = self.at_eof;
+        d
+    }
+    /// Record a non-fatal diagnostic where the driver is, an `Error` severity becomes `Warning`
+    #[inline]
+    pub fn warn(&mut self, d: Diagnostic<E>) {
+        let mut d = self.locate(d);
+        if d.severity == Severity::Error {
+            d.severity = Severity::Warning;
+        }
+        self.warnings.push(d);
+    }
+    /// Move the warnings out, they are held back while an alternative can still fail
+    #[inline]
+    pub(crate) fn take_warnings(&mut self, out: &mut Vec<Diagnostic<E>>) {
+        if self.alts.is_empty() {
+            out.append(&mut self.warnings);
+        }
+    }
+    /// Take the warnings once the parse is over, dropping those of alternatives left pending
+    #[inline]
+    pub(crate) fn settled_warnings(&mut self) -> Vec<Diagnostic<E>> {
+        if let Some(a) = self.alts.first() {
+            self.warnings.truncate(a.warned);
         }
+        std::mem::take(&mut self.warnings)
+    }
+    /// The input source failed, report it at the position of the failed read
+    #[inline]
+    pub(crate) fn input_error(&mut self, error: E) {
+        let d = Diagnostic::new(error, self.names.clone(), self.read, false);
+        self.errors.push(d);
+    }
+    /// Names of the frames from the root to the top
+    #[inline]
+    pub fn stack_names(&self) -> &[&'static str] {
+        &self.names
+    }
+    #[inline]
+    pub fn depth(&self) -> usize {
+        self.states.len()
+    }
+    #[inline]
+    pub fn top_name(&self) -> Option<&'static str> {
+        self.names.last().copied()
+    }
+    /// Go back to a checkpoint, pending redos and alternatives are dropped
+    pub fn restore(&mut self, cp: Checkpoint<'a, I, D, F>) {
+        self.states = cp.states;
+        self.names = cp.names;
+        self.pooled = vec![None; self.states.len()];
+        self.data = cp.data;
+        self.pending = false;
+        self.recovering = None;
+        self.errored = None;
+        self.alts.clear();
+        self.log.clear();
+        self.ahead.clear();
+        self.peek = None;
+        self.current = None;
+        self.eaten.clear();
+        self.yielded.clear();
+        self.expected = None;
+    }
+    /// Merge what is expected at the current input, unless something failed further on
+    fn expect(&mut self, set: Vec<&'static str>, span: Option<Range<usize>>) {
+        match &mut self.expected {
+            Some(x) if x.index > self.index => {}
+            Some(x) if x.index == self.index => {
+                for e in set {
+                    if !x.set.contains(&e) {
+                        x.set.push(e);
+                    }
+                }
+                if x.span.is_none() {
+                    x.span = span;
+                }
+            }
+            _ => {
+                self.expected = Some(Expected {
+                    set,
+                    span,
+                    stack: self.names.clone(),
+                    index: self.index,
+                    eof: self.at_eof,
+                })
+            }
+        }
+    }
+    /// Take the next value emitted with `RecunsFlow::Yield`, they are held back while an alternative can still fail
+    #[inline]
+    pub fn take_yield(&mut self) -> Option<Box<dyn Any>> {
+        if !self.alts.is_empty() {
+            return None;
+        }
+        self.yielded.pop_front()
     }
 }
-impl<'a, 'b, I, D> State<'a, 'b, I, D> {
+impl<'a, I, D, E: From<RecunsError>, F: Frames<Input = I, Data = D, Error = E>>
+    State<'a, I, D, E, F>
+{
+    /// Push a recycled frame of the requested type, or a new one if there is none
     #[inline]
-    pub fn new(stop_when_err: bool, data: D, errors: &'b mut Vec<Arc<Error>>) -> Self {
-        Self {
-            stop_when_err,
+    pub(crate) fn push_pooled(&mut self, p: Pooled<F>, name: &'static str) {
+        let f = match self
+            .pool
+            .as_mut()
+            .and_then(|pool| pool.get_mut(&p.id)?.pop())
+        {
+            Some(f) => f,
+            None => (p.new)(),
+        };
+        self.states.push(f);
+        self.names.push(name);
+        self.pooled.push(Some(p.id));
+    }
+    /// Pop the top frame, giving it back to the pool if it came from there and can `reset`
+    #[inline]
+    pub(crate) fn end(&mut self) {
+        self.names.pop();
+        let id = self.pooled.pop().flatten();
+        if let (Some(mut f), Some(id), Some(pool)) = (self.states.pop(), id, &mut self.pool) {
+            if f.reset() {
+                pool.entry(id).or_default().push(f);
+            }
+        }
+    }
+    #[inline]
+    pub(crate) fn truncate(&mut self, len: usize) {
+        while self.states.len() > len {
+            self.end();
+        }
+    }
+}
+impl<'a, I, D: Clone, E: From<RecunsError>, F: Frames<Input = I, Data = D, Error = E>>
+    State<'a, I, D, E, F>
+{
+    /// Snapshot the stack and data, `None` if some frame can't `fork`
+    pub fn checkpoint(&self) -> Option<Checkpoint<'a, I, D, F>> {
+        let states = self
+            .states
+            .iter()
+            .map(F::fork)
+            .collect::<Option<Vec<_>>>()?;
+        Some(Checkpoint {
+            states,
+            names: self.names.clone(),
+            data: self.data.clone(),
+            _frames: PhantomData,
+            _input: PhantomData,
+        })
+    }
+}
 
-            data,
+pub struct Checkpoint<'a, I, D, F = RecunsFrame<'a, I, D>> {
+    states: Vec<F>,
+    names: Vec<&'static str>,
+    data: D,
+    _frames: PhantomData<&'a ()>,
+    _input: PhantomData<fn() -> I>,
+}
+impl<'a, I, D: Debug, F> Debug for Checkpoint<'a, I, D, F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Checkpoint")
+            .field("stack", &self.names)
+            .field("data", &self.data)
+            .finish()
+    }
+}
 
-            states: vec![],
-            queue: vec![],
-            errors,
+/// The inputs read ahead for `Recuns::on_peek`
+pub struct Lookahead<'s, I> {
+    ahead: &'s VecDeque<Option<I>>,
+    len: usize,
+    consumed: usize,
+}
+impl<'s, I> Lookahead<'s, I> {
+    /// The `k`th input after the current one, `None` past the end of input
+    #[inline]
+    pub fn get(&self, k: usize) -> Option<&I> {
+        if k < self.len {
+            self.ahead[k].as_ref()
+        } else {
+            None
         }
     }
     #[inline]
-    pub fn push(&mut self, rec: Box<dyn 'a + Recuns<Input = I, Data = D>>) {
-        self.states.push(rec)
+    pub fn len(&self) -> usize {
+        self.len
     }
     #[inline]
-    pub unsafe fn pop(&mut self) -> Option<Box<dyn 'a + Recuns<Input = I, Data = D>>> {
-        self.states.pop()
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// Whether the input ends right after the read ahead inputs
+    #[inline]
+    pub fn is_eof(&self) -> bool {
+        self.len < self.ahead.len()
+    }
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = &I> {
+        self.ahead.iter().take(self.len).flatten()
+    }
+    /// Consume the next `n` inputs, they will not be dispatched
+    #[inline]
+    pub fn consume(&mut self, n: usize) {
+        self.consumed = (self.consumed + n).min(self.len);
+    }
+}
+impl<'s, I: Debug> Debug for Lookahead<'s, I> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
     }
 }
 
+/// Redos of the current input since the stack last reached a new depth
+#[derive(Debug, Default)]
+pub(crate) struct Spin {
+    count: usize,
+    min: usize,
+    max: usize,
+    /// Frames that checked the input since then
+    names: Vec<&'static str>,
+}
+impl Spin {
+    #[inline]
+    fn reset(&mut self, depth: usize) {
+        self.count = 0;
+        self.min = depth;
+        self.max = depth;
+        self.names.clear();
+    }
+}
+
+/// The merged `RecunsFlow::Expect`s at one input, located where the first of them was
+/// and spanned like the first one with a span
+#[derive(Debug)]
+pub(crate) struct Expected {
+    set: Vec<&'static str>,
+    span: Option<Range<usize>>,
+    stack: Vec<&'static str>,
+    index: usize,
+    eof: bool,
+}
+
+/// A pending `RecunsFlow::Alt`, kept until its frame ends or every alternative failed
+pub(crate) struct AltPoint<D, F> {
+    len: usize,
+    /// Where the input that started it goes in the log once consumed
+    pos: usize,
+    /// How many values were yielded before it
+    out: usize,
+    /// How many warnings were raised before it
+    warned: usize,
+    data: D,
+    snapshot: fn(&D) -> D,
+    names: Vec<&'static str>,
+    alts: AltIter<F>,
+}
+
 #[inline]
-fn call<'a, 'b, I: Clone + 'a, D>(s: &mut State<'a, 'b, I, D>, input: I, eof: bool) -> Option<()> {
+pub(crate) fn call<
+    'a,
+    I: 'a,
+    D,
+    E: From<RecunsError>,
+    F: Frames<Input = I, Data = D, Error = E>,
+>(
+    s: &mut State<'a, I, D, E, F>,
+) -> Option<()> {
+    let eof = match &s.current {
+        Some(i) => i.is_none(),
+        None => return Some(()),
+    };
+    s.at_eof = eof;
+    burn(s)?;
+    spin(s)?;
+    if s.recovering.is_some() && !sync(s) {
+        settle(s, false);
+        return peek(s);
+    }
     let r = s.states.last_mut()?;
-    let r = r.check(input.clone(), &mut s.data, eof);
+    let r = match &s.current {
+        Some(Some(i)) => r.check(i, &mut s.data),
+        _ => r.on_eof(&mut s.data),
+    };
+
+    let again = at_eof(s, &r, eof)?;
+    let redo = flow(s, r)? || again;
+    settle(s, redo);
+    peek(s)
+}
 
-    #[inline(always)]
-    fn redo<'a, 'b, I: Clone + 'a, D>(s: &mut State<'a, 'b, I, D>, input: I, eof: bool) {
-        s.queue.push(Box::new(move |this| {
-            call(this, input.clone(), eof);
-        }));
+/// Take one step of fuel, aborting whatever `stop_when_err` says once it runs out
+#[inline]
+fn burn<I, D, E: From<RecunsError>, F>(s: &mut State<'_, I, D, E, F>) -> Option<()> {
+    match &mut s.fuel {
+        Some(0) => {
+            let d = s.locate(RecunsError::OutOfFuel.into());
+            s.errors.push(d);
+            None
+        }
+        Some(f) => {
+            *f -= 1;
+            Some(())
+        }
+        None => Some(()),
     }
+}
 
+/// Count a check of the current input, aborting once it is redone too often without progress
+#[inline]
+fn spin<I, D, E: From<RecunsError>, F>(s: &mut State<'_, I, D, E, F>) -> Option<()> {
+    let depth = s.states.len();
+    let spin = &mut s.spin;
+    if depth < spin.min || depth > spin.max {
+        spin.min = spin.min.min(depth);
+        spin.max = spin.max.max(depth);
+        spin.count = 0;
+        spin.names.clear();
+    }
+    if let Some(name) = s.names.last() {
+        if !spin.names.contains(name) {
+            spin.names.push(name);
+        }
+    }
+    spin.count += 1;
+    match s.max_redo {
+        Some(max) if spin.count > max => {
+            let names = std::mem::take(&mut spin.names);
+            let d = s.locate(RecunsError::Spin(names).into());
+            s.errors.push(d);
+            None
+        }
+        _ => Some(()),
+    }
+}
+
+/// The end of input is handed down the stack until every frame has ended or failed,
+/// returns whether it has to be redone on the next frame
+#[inline]
+fn at_eof<I, D, E: From<RecunsError>, F>(
+    s: &mut State<'_, I, D, E, F>,
+    r: &RecunsFlow<I, D, E, F>,
+    eof: bool,
+) -> Option<bool> {
+    if !eof {
+        return Some(false);
+    }
     match r {
-        RecunsFlow::End => unsafe {
-            s.pop();
-        },
-        RecunsFlow::EndReDo => unsafe {
-            s.pop();
-            redo(s, input, eof);
-        },
-        RecunsFlow::Call(f, _) => {
-            s.push(f);
-            redo(s, input, eof);
-        }
-        RecunsFlow::CallNext(f, _) => s.push(f),
-        RecunsFlow::Mov(f, _) => unsafe {
-            s.pop();
-            s.push(f);
-            redo(s, input, eof);
-        },
-        RecunsFlow::MovNext(f, _) => unsafe {
-            s.pop();
-            s.push(f);
-        },
-        RecunsFlow::Err(err) => {
-            s.errors.push(err);
-            if s.stop_when_err {
-                return None;
-            }
+        RecunsFlow::None => err(s, RecunsError::UnfinishedAtEof),
+        RecunsFlow::Fail | RecunsFlow::Peek(_) | RecunsFlow::Err(_) | RecunsFlow::Diag(_) => {
+            Some(false)
         }
-        RecunsFlow::None => (),
+        RecunsFlow::Yield(_, then) | RecunsFlow::Expect(_, _, then) | RecunsFlow::Warn(_, then) => {
+            at_eof(s, then, eof)
+        }
+        _ => Some(true),
     }
+}
 
-    Some(())
+/// Unwind to the nearest frame that syncs on the current input, returns `false` if none does
+/// or if the input has to be skipped
+///
+/// At the end of input only a frame that failed on it is skipped, the others get `on_eof` as usual.
+/// A frame syncing on the input that already errored at its depth or below skips it,
+/// so each bad input is reported once
+#[inline]
+fn sync<'a, I: 'a, D, E: From<RecunsError>, F: Frames<Input = I, Data = D, Error = E>>(
+    s: &mut State<'a, I, D, E, F>,
+) -> bool {
+    let data = &mut s.data;
+    let at = match &s.current {
+        Some(Some(i)) => s.states.iter_mut().rposition(|f| f.sync(i, data)),
+        _ if s.recovering == Some(true) => s.states.len().checked_sub(2),
+        _ => s.states.len().checked_sub(1),
+    };
+    match at {
+        Some(at) => {
+            s.truncate(at + 1);
+            s.recovering = None;
+            let again = matches!(s.errored, Some((depth, index))
+                if !s.at_eof && index == s.index && at + 1 >= depth);
+            !again
+        }
+        None => false,
+    }
 }
 
-macro_rules! do_loop {
-    { $s:ident ; $data:expr, $root:expr, $stop_when_err:expr, $next:expr ; $($b:block)? } => {
-        let mut errors = vec![];
-        let mut $s: State<'a, '_, I, D> = State::new($stop_when_err, $data, &mut errors);
-        $s.push(Box::new($root));
-        let mut finish = false;
-        loop {
-            $($b;)?
+/// Drop the alternatives that have ended, then schedule the redo or consume the current input
+#[inline]
+fn settle<'a, I: 'a, D, E: From<RecunsError>, F: Frames<Input = I, Data = D, Error = E>>(
+    s: &mut State<'a, I, D, E, F>,
+    redo: bool,
+) {
+    while let Some(a) = s.alts.last() {
+        if s.states.len() > a.len {
+            break;
+        }
+        s.alts.pop();
+        if s.alts.is_empty() {
+            s.log.clear();
+        }
+    }
+    if redo && !s.states.is_empty() && s.current.is_some() {
+        s.pending = true;
+        return;
+    }
+    if let Some(i) = s.current.take() {
+        if !s.alts.is_empty() {
+            s.log.push(i);
+            s.log.extend(s.eaten.drain(..).map(Some));
+            return;
+        }
+    }
+    s.eaten.clear();
+    if s.alts.is_empty() {
+        s.expected = None;
+    }
+}
 
-            if !$s.queue.is_empty() {
-                let mut q = $s.queue.pop().unwrap();
-                q(&mut $s);
-                continue;
-            }
+/// Run the pending `on_peek` once enough inputs are read ahead, again while it asks to peek
+/// no further than is already read, each run counts like a redo
+fn peek<'a, I: 'a, D, E: From<RecunsError>, F: Frames<Input = I, Data = D, Error = E>>(
+    s: &mut State<'a, I, D, E, F>,
+) -> Option<()> {
+    loop {
+        let k = match &s.peek {
+            Some((_, k, _)) => *k,
+            None => return Some(()),
+        };
+        let len = s.ahead.iter().take_while(|i| i.is_some()).count();
+        if len < k && len == s.ahead.len() {
+            return Some(());
+        }
+        let (input, _, index) = s.peek.take().unwrap();
+        s.index = index;
+        s.at_eof = false;
+        burn(s)?;
+        spin(s)?;
 
-            if finish {
-                break;
-            }
+        let r = s.states.last_mut()?;
+        let mut ahead = Lookahead {
+            ahead: &s.ahead,
+            len,
+            consumed: 0,
+        };
+        let r = r.on_peek(&input, &mut ahead, &mut s.data);
+        let consumed = ahead.consumed;
+        s.eaten.extend(s.ahead.drain(..consumed).flatten());
+        s.current = Some(Some(input));
 
-            let c = $next(&mut $s.data);
-            if c.is_none() {
-                finish = true;
-                let r = call(&mut $s, Default::default(), true);
-                if r.is_none() {
-                    break;
-                }
-                continue;
+        let redo = flow(s, r)?;
+        settle(s, redo);
+    }
+}
+
+/// Dispatch a new input, `None` is the end of input
+#[inline]
+pub(crate) fn input<
+    'a,
+    I: 'a,
+    D,
+    E: From<RecunsError>,
+    F: Frames<Input = I, Data = D, Error = E>,
+>(
+    s: &mut State<'a, I, D, E, F>,
+    input: Option<I>,
+) -> Option<()> {
+    if s.peek.is_some() {
+        s.ahead.push_back(input);
+        return peek(s);
+    }
+    s.index = s.read.saturating_sub(s.ahead.len() + 1);
+    s.spin.reset(s.states.len());
+    s.current = Some(input);
+    call(s)
+}
+
+macro_rules! too_deep {
+    { $s:ident } => {
+        if let Some(max) = $s.max_depth {
+            if $s.states.len() >= max {
+                return err($s, RecunsError::MaxDepth(max));
             }
-            let c: RecunsResult<I> = c.unwrap();
-            let c = match c {
-                Ok(c) => c,
-                Err(err) => {
-                    $s.errors.push(err);
-                    return Err($s.errors.clone());
-                }
-            };
+        }
+    };
+}
 
-            let r = call(&mut $s, c, false);
-            if r.is_none() {
-                break;
+/// Apply a flow to the stack, returns whether the input needs to be redone
+fn flow<'a, I: 'a, D, E: From<RecunsError>, F: Frames<Input = I, Data = D, Error = E>>(
+    s: &mut State<'a, I, D, E, F>,
+    r: RecunsFlow<I, D, E, F>,
+) -> Option<bool> {
+    Some(match r {
+        RecunsFlow::End => {
+            s.end();
+            false
+        }
+        RecunsFlow::EndReDo => {
+            s.end();
+            true
+        }
+        RecunsFlow::Call(f, name) => {
+            too_deep!(s);
+            s.push(f, name);
+            true
+        }
+        RecunsFlow::CallNext(f, name) => {
+            too_deep!(s);
+            s.push(f, name);
+            false
+        }
+        RecunsFlow::Mov(f, name) => {
+            s.end();
+            s.push(f, name);
+            true
+        }
+        RecunsFlow::MovNext(f, name) => {
+            s.end();
+            s.push(f, name);
+            false
+        }
+        RecunsFlow::CallPooled(p, name) => {
+            too_deep!(s);
+            s.push_pooled(p, name);
+            true
+        }
+        RecunsFlow::CallNextPooled(p, name) => {
+            too_deep!(s);
+            s.push_pooled(p, name);
+            false
+        }
+        RecunsFlow::Ret(v) => {
+            s.end();
+            ret(s, v)?
+        }
+        RecunsFlow::RetReDo(v) => {
+            s.end();
+            ret(s, v)?;
+            true
+        }
+        RecunsFlow::Alt(alts, snapshot, _) => {
+            too_deep!(s);
+            let names = alts.iter().map(|(_, name)| *name).collect();
+            let mut alts = alts.into_iter();
+            let (f, name) = match alts.next() {
+                Some(f) => f,
+                None => return err(s, RecunsError::NoAlt(names)),
+            };
+            s.alts.push(AltPoint {
+                len: s.states.len(),
+                pos: s.log.len(),
+                out: s.yielded.len(),
+                warned: s.warnings.len(),
+                data: snapshot(&s.data),
+                snapshot,
+                names,
+                alts,
+            });
+            s.push(f, name);
+            true
+        }
+        RecunsFlow::Fail => return fail(s),
+        RecunsFlow::Expect(set, span, then) => {
+            s.expect(set, span);
+            return flow(s, *then);
+        }
+        RecunsFlow::Peek(k) => match s.current.take() {
+            Some(Some(i)) => {
+                s.peek = Some((i, k, s.index));
+                false
+            }
+            current => {
+                s.current = current;
+                return err(s, RecunsError::PeekAtEof);
             }
+        },
+        RecunsFlow::Err(e) => {
+            let d = s.diagnostic(e);
+            return report(s, d);
         }
-        if !$s.errors.is_empty() {
-            return Err($s.errors.clone());
+        RecunsFlow::Diag(d) => {
+            let d = s.locate(*d);
+            return report(s, d);
         }
-        Ok(Some($s.data))
-    };
+        RecunsFlow::Warn(d, then) => {
+            s.warn(*d);
+            return flow(s, *then);
+        }
+        RecunsFlow::Yield(v, then) => {
+            s.yielded.push_back(v);
+            return flow(s, *then);
+        }
+        RecunsFlow::None => false,
+    })
 }
 
 #[inline]
-pub fn do_loop_cancel_on_loop<'a, I: Clone + Default + 'a, D>(
-    data: D,
-    root: impl Recuns<Data = D, Input = I> + 'a,
-    stop_when_err: bool,
-    mut next: impl FnMut(&mut D) -> Option<RecunsResult<I>>,
-    mut cancel: impl FnMut() -> bool,
-    mut on_loop: impl FnMut(&mut State<I, D>),
-) -> RecunsResultErrs<Option<D>> {
-    do_loop! {
-        s ;
-        data, root, stop_when_err, next ;
-        {
-            if cancel() {
-                return Ok(None);
+fn err<I, D, E: From<RecunsError>, F>(
+    s: &mut State<'_, I, D, E, F>,
+    e: RecunsError,
+) -> Option<bool> {
+    let d = s.locate(e.into());
+    report(s, d)
+}
+
+/// Record an error, then recover on the input if `recover` is set or stop the driver if `stop_when_err` is
+#[inline]
+fn report<I, D, E, F>(s: &mut State<'_, I, D, E, F>, d: Diagnostic<E>) -> Option<bool> {
+    s.errors.push(d);
+    if s.recover {
+        s.recovering = Some(s.at_eof);
+        s.errored = Some((s.states.len(), s.index));
+        return Some(true);
+    }
+    if s.stop_when_err {
+        return None;
+    }
+    Some(false)
+}
+
+/// Rewind to the nearest pending alternative and start the next one
+fn fail<'a, I: 'a, D, E: From<RecunsError>, F: Frames<Input = I, Data = D, Error = E>>(
+    s: &mut State<'a, I, D, E, F>,
+) -> Option<bool> {
+    if s.alts.is_empty() {
+        return unmatched(s, RecunsError::FailWithoutAlt);
+    }
+    // The current input and the ones read after it are dispatched again
+    for i in s.eaten.drain(..).rev() {
+        s.ahead.push_front(Some(i));
+    }
+    if let Some(i) = s.current.take() {
+        s.ahead.push_front(i);
+    }
+    while let Some(mut a) = s.alts.pop() {
+        s.truncate(a.len);
+        s.yielded.truncate(a.out);
+        s.warnings.truncate(a.warned);
+        s.pending = false;
+        let mut replay: VecDeque<_> = s.log.split_off(a.pos).into();
+        replay.append(&mut s.ahead);
+        s.ahead = replay;
+        match a.alts.next() {
+            Some((f, name)) => {
+                s.data = (a.snapshot)(&a.data);
+                s.push(f, name);
+                s.alts.push(a);
+                return Some(false);
+            }
+            None => {
+                s.data = a.data;
+                if s.alts.is_empty() {
+                    // Nothing matched the input that started it, skip it
+                    s.ahead.pop_front();
+                    s.log.clear();
+                    return unmatched(s, RecunsError::NoAlt(a.names));
+                }
             }
-            on_loop(&mut s);
         }
     }
+    Some(false)
 }
 
+/// Report `e`, or what was expected instead if a frame said so
 #[inline]
-pub fn do_loop_on_loop<'a, I: Clone + Default + 'a, D>(
-    data: D,
-    root: impl Recuns<Data = D, Input = I> + 'a,
-    stop_when_err: bool,
-    mut next: impl FnMut(&mut D) -> Option<RecunsResult<I>>,
-    mut on_loop: impl FnMut(&mut State<I, D>),
-) -> RecunsResultErrs<Option<D>> {
-    do_loop! {
-        s ;
-        data, root, stop_when_err, next ;
-        {
-            on_loop(&mut s);
+fn unmatched<I, D, E: From<RecunsError>, F>(
+    s: &mut State<'_, I, D, E, F>,
+    e: RecunsError,
+) -> Option<bool> {
+    match s.expected.take() {
+        Some(x) => {
+            let mut d: Diagnostic<E> = RecunsError::Expected(x.set).into();
+            d.span = x.span;
+            d.stack = x.stack;
+            d.index = x.index;
+            d.eof = x.eof;
+            report(s, d)
         }
+        None => err(s, e),
     }
 }
 
 #[inline]
-pub fn do_loop_cancel<'a, I: Clone + Default + 'a, D>(
-    data: D,
-    root: impl Recuns<Data = D, Input = I> + 'a,
-    stop_when_err: bool,
-    mut next: impl FnMut(&mut D) -> Option<RecunsResult<I>>,
-    mut cancel: impl FnMut() -> bool,
-) -> RecunsResultErrs<Option<D>> {
-    do_loop! {
-        s ;
-        data, root, stop_when_err, next ;
-        {
-            if cancel() {
-                return Ok(None);
-            }
+fn ret<'a, I: 'a, D, E: From<RecunsError>, F: Frames<Input = I, Data = D, Error = E>>(
+    s: &mut State<'a, I, D, E, F>,
+    v: Box<dyn Any>,
+) -> Option<bool> {
+    match s.states.last_mut() {
+        Some(p) => {
+            let r = p.on_ret(v, &mut s.data);
+            flow(s, r)
+        }
+        None => Some(false),
+    }
+}
+
+/// Run the pending redo or a replayed input, `None` if there is nothing to run
+#[inline]
+pub(crate) fn redo<
+    'a,
+    I: 'a,
+    D,
+    E: From<RecunsError>,
+    F: Frames<Input = I, Data = D, Error = E>,
+>(
+    s: &mut State<'a, I, D, E, F>,
+) -> Option<Option<()>> {
+    if s.pending {
+        s.pending = false;
+        if burn(s).is_none() {
+            return Some(None);
         }
+        return Some(call(s));
     }
+    if s.peek.is_some() {
+        return None;
+    }
+    let i = s.ahead.pop_front()?;
+    Some(input(s, i))
 }
 
 #[inline]
-pub fn do_loop<'a, I: Clone + Default + 'a, D>(
+pub fn do_loop_cancel_on_loop<'a, I: 'a, D, E: From<RecunsError> + 'a>(
     data: D,
-    root: impl Recuns<Data = D, Input = I> + 'a,
+    root: impl Recuns<E, Data = D, Input = I> + 'a,
     stop_when_err: bool,
-    mut next: impl FnMut(&mut D) -> Option<RecunsResult<I>>,
-) -> RecunsResultErrs<Option<D>> {
-    do_loop! {
-        s ;
-        data, root, stop_when_err, next ;
-    }
-    // let mut errors = vec![];
-    // let mut s: State<'_, '_, I, D> = State::new(stop_when_err, data, &mut errors);
-    // s.push(Box::new(root));
-    // let mut finish = false;
-    // loop {
-    //     if !s.queue.is_empty() {
-    //         let mut q = s.queue.pop().unwrap();
-    //         q(&mut s);
-    //         continue;
-    //     }
-
-    //     if finish {
-    //         break;
-    //     }
-
-    //     let c = next(&mut s.data);
-    //     if c.is_none() {
-    //         finish = true;
-    //         let r = call(&mut s, Default::default(), true);
-    //         if r.is_none() {
-    //             break;
-    //         }
-    //         continue;
-    //     }
-    //     let c: RecunsResult<I> = c.unwrap();
-    //     let c = match c {
-    //         Ok(c) => c,
-    //         Err(err) => {
-    //             s.errors.push(err);
-    //             return Err(s.errors.clone());
-    //         }
-    //     };
-
-    //     let r = call(&mut s, c, false);
-    //     if r.is_none() {
-    //         break;
-    //     }
-    // }
-    // if !s.errors.is_empty() {
-    //     return Err(s.errors.clone());
-    // }
-    // Ok(Some(s.data))
-}
-
-macro_rules! do_iter { //$($b;)?
-    { $s:ident ; $data:expr, $root:expr, $stop_when_err:expr, $next:expr, $errors:expr, $yields:expr ; $($b:block)? } => {
-        let mut $s: State<'_, '_, I, D> = State::new($stop_when_err, $data, $errors);
-        $s.push(Box::new($root));
-        let mut finish = false;
-        let mut is_yield = false;
-        let mut yield_val: VecDeque<U> = VecDeque::new();
-        let i = DoLoopIter::new(move || -> Option<U> {
-            loop {
-                $($b;)?
-
-                if !yield_val.is_empty() {
-                    let rv = yield_val.pop_front();
-                    if let Some(v) = rv {
-                        return Some(v);
-                    }
-                }
-                if !is_yield {
-                    let r = $yields(&mut $s.data);
-                    if let Some(mut v) = r {
-                        is_yield = true;
-                        let rv = v.pop_front();
-                        if !v.is_empty() {
-                            yield_val = v;
-                        }
-                        if let Some(v) = rv {
-                            return Some(v);
-                        }
-                    }
-                }
-                is_yield = false;
+    next: impl FnMut(&mut D) -> Option<RecunsResult<I, E>>,
+    cancel: impl 'a + FnMut() -> bool,
+    on_loop: impl 'a + FnMut(&mut State<I, D, E>),
+) -> Outcome<Option<D>, E> {
+    Driver::new(data, root)
+        .stop_when_err(stop_when_err)
+        .cancel(cancel)
+        .on_loop(on_loop)
+        .run(next)
+}
 
-                if !$s.queue.is_empty() {
-                    let mut q = $s.queue.pop().unwrap();
-                    q(&mut $s);
-                    continue;
-                }
+#[inline]
+pub fn do_loop_on_loop<'a, I: 'a, D, E: From<RecunsError> + 'a>(
+    data: D,
+    root: impl Recuns<E, Data = D, Input = I> + 'a,
+    stop_when_err: bool,
+    next: impl FnMut(&mut D) -> Option<RecunsResult<I, E>>,
+    on_loop: impl 'a + FnMut(&mut State<I, D, E>),
+) -> Outcome<Option<D>, E> {
+    Driver::new(data, root)
+        .stop_when_err(stop_when_err)
+        .on_loop(on_loop)
+        .run(next)
+}
 
-                if finish {
-                    break;
-                }
+#[inline]
+pub fn do_loop_cancel<'a, I: 'a, D, E: From<RecunsError> + 'a>(
+    data: D,
+    root: impl Recuns<E, Data = D, Input = I> + 'a,
+    stop_when_err: bool,
+    next: impl FnMut(&mut D) -> Option<RecunsResult<I, E>>,
+    cancel: impl 'a + FnMut() -> bool,
+) -> Outcome<Option<D>, E> {
+    Driver::new(data, root)
+        .stop_when_err(stop_when_err)
+        .cancel(cancel)
+        .run(next)
+}
 
-                let c = $next(&mut $s.data);
-                if c.is_none() {
-                    finish = true;
-                    let r = call(&mut $s, Default::default(), false);
-                    if r.is_none() {
-                        break;
-                    }
-                    continue;
-                }
-                let c: RecunsResult<I> = c.unwrap();
-                let c = match c {
-                    Ok(c) => c,
-                    Err(err) => {
-                        $s.errors.push(err);
-                        return None;
-                    }
-                };
+#[inline]
+pub fn do_loop<'a, I: 'a, D, E: From<RecunsError> + 'a>(
+    data: D,
+    root: impl Recuns<E, Data = D, Input = I> + 'a,
+    stop_when_err: bool,
+    next: impl FnMut(&mut D) -> Option<RecunsResult<I, E>>,
+) -> Outcome<Option<D>, E> {
+    Driver::new(data, root)
+        .stop_when_err(stop_when_err)
+        .run(next)
+}
 
-                let r = call(&mut $s, c, false);
-                if r.is_none() {
-                    break;
-                }
-            }
-            None
-        });
-        i
-    };
+#[cfg(feature = "async")]
+pub async fn do_loop_async<'a, I: 'a, D, E: From<RecunsError> + 'a>(
+    data: D,
+    root: impl Recuns<E, Data = D, Input = I> + 'a,
+    stop_when_err: bool,
+    next: impl Stream<Item = RecunsResult<I, E>> + Unpin,
+) -> Outcome<Option<D>, E> {
+    Driver::new(data, root)
+        .stop_when_err(stop_when_err)
+        .run_async(next)
+        .await
 }
 
 #[inline]
-pub fn do_iter_cancel_on_loop<'a, I: Clone + Default + 'a, D: 'a, U: 'a>(
+#[allow(clippy::too_many_arguments)]
+pub fn do_iter_cancel_on_loop<'a, I: 'a, D: 'a, E: From<RecunsError> + 'a, U: 'a>(
     data: D,
-    root: impl Recuns<Data = D, Input = I> + 'a,
+    root: impl Recuns<E, Data = D, Input = I> + 'a,
     stop_when_err: bool,
-    errors: &'a mut Vec<Arc<Error>>,
-    mut next: impl 'a + FnMut(&mut D) -> Option<RecunsResult<I>>,
-    mut yields: impl 'a + FnMut(&mut D) -> Option<VecDeque<U>>,
-    mut cancel: impl 'a + FnMut() -> bool,
-    mut on_loop: impl 'a + FnMut(&mut State<I, D>),
+    errors: &'a mut Vec<Diagnostic<E>>,
+    warnings: &'a mut Vec<Diagnostic<E>>,
+    next: impl 'a + FnMut(&mut D) -> Option<RecunsResult<I, E>>,
+    yields: impl 'a + FnMut(&mut D) -> Option<VecDeque<U>>,
+    cancel: impl 'a + FnMut() -> bool,
+    on_loop: impl 'a + FnMut(&mut State<I, D, E>),
 ) -> impl 'a + Iterator<Item = U> {
-    do_iter! {
-        s ;
-        data, root, stop_when_err, next, errors, yields ;
-        {
-            if cancel() {
-                return None;
-            }
-            on_loop(&mut s);
-        }
-    }
+    Driver::new(data, root)
+        .stop_when_err(stop_when_err)
+        .cancel(cancel)
+        .on_loop(on_loop)
+        .iter(errors, warnings, next, yields)
 }
 
 #[inline]
-pub fn do_iter_on_loop<'a, I: Clone + Default + 'a, D: 'a, U: 'a>(
+#[allow(clippy::too_many_arguments)]
+pub fn do_iter_on_loop<'a, I: 'a, D: 'a, E: From<RecunsError> + 'a, U: 'a>(
     data: D,
-    root: impl Recuns<Data = D, Input = I> + 'a,
+    root: impl Recuns<E, Data = D, Input = I> + 'a,
     stop_when_err: bool,
-    errors: &'a mut Vec<Arc<Error>>,
-    mut next: impl 'a + FnMut(&mut D) -> Option<RecunsResult<I>>,
-    mut yields: impl 'a + FnMut(&mut D) -> Option<VecDeque<U>>,
-    mut on_loop: impl 'a + FnMut(&mut State<I, D>),
+    errors: &'a mut Vec<Diagnostic<E>>,
+    warnings: &'a mut Vec<Diagnostic<E>>,
+    next: impl 'a + FnMut(&mut D) -> Option<RecunsResult<I, E>>,
+    yields: impl 'a + FnMut(&mut D) -> Option<VecDeque<U>>,
+    on_loop: impl 'a + FnMut(&mut State<I, D, E>),
 ) -> impl 'a + Iterator<Item = U> {
-    do_iter! {
-        s ;
-        data, root, stop_when_err, next, errors, yields ;
-        {
-            on_loop(&mut s);
-        }
-    }
+    Driver::new(data, root)
+        .stop_when_err(stop_when_err)
+        .on_loop(on_loop)
+        .iter(errors, warnings, next, yields)
 }
 
 #[inline]
-pub fn do_iter_cancel<'a, I: Clone + Default + 'a, D: 'a, U: 'a>(
+#[allow(clippy::too_many_arguments)]
+pub fn do_iter_cancel<'a, I: 'a, D: 'a, E: From<RecunsError> + 'a, U: 'a>(
     data: D,
-    root: impl Recuns<Data = D, Input = I> + 'a,
+    root: impl Recuns<E, Data = D, Input = I> + 'a,
     stop_when_err: bool,
-    errors: &'a mut Vec<Arc<Error>>,
-    mut next: impl 'a + FnMut(&mut D) -> Option<RecunsResult<I>>,
-    mut yields: impl 'a + FnMut(&mut D) -> Option<VecDeque<U>>,
-    mut cancel: impl 'a + FnMut() -> bool,
+    errors: &'a mut Vec<Diagnostic<E>>,
+    warnings: &'a mut Vec<Diagnostic<E>>,
+    next: impl 'a + FnMut(&mut D) -> Option<RecunsResult<I, E>>,
+    yields: impl 'a + FnMut(&mut D) -> Option<VecDeque<U>>,
+    cancel: impl 'a + FnMut() -> bool,
 ) -> impl 'a + Iterator<Item = U> {
-    do_iter! {
-        s ;
-        data, root, stop_when_err, next, errors, yields ;
-        {
-            if cancel() {
-                return None;
-            }
-        }
-    }
+    Driver::new(data, root)
+        .stop_when_err(stop_when_err)
+        .cancel(cancel)
+        .iter(errors, warnings, next, yields)
 }
 
 #[inline]
-pub fn do_iter<'a, I: Clone + Default + 'a, D: 'a, U: 'a>(
+pub fn do_iter<'a, I: 'a, D: 'a, E: From<RecunsError> + 'a, U: 'a>(
     data: D,
-    root: impl Recuns<Data = D, Input = I> + 'a,
+    root: impl Recuns<E, Data = D, Input = I> + 'a,
     stop_when_err: bool,
-    errors: &'a mut Vec<Arc<Error>>,
-    mut next: impl 'a + FnMut(&mut D) -> Option<RecunsResult<I>>,
-    mut yields: impl 'a + FnMut(&mut D) -> Option<VecDeque<U>>,
+    errors: &'a mut Vec<Diagnostic<E>>,
+    warnings: &'a mut Vec<Diagnostic<E>>,
+    next: impl 'a + FnMut(&mut D) -> Option<RecunsResult<I, E>>,
+    yields: impl 'a + FnMut(&mut D) -> Option<VecDeque<U>>,
 ) -> impl 'a + Iterator<Item = U> {
-    do_iter! {
-        s ;
-        data, root, stop_when_err, next, errors, yields ;
-    }
-    // let mut s: State<'_, '_, I, D> = State::new(stop_when_err, data, errors);
-    // s.push(Box::new(root));
-    // let mut finish = false;
-    // let mut is_yield = false;
-    // let mut yield_val: VecDeque<U> = VecDeque::new();
-    // let i = DoLoopIter::new(move || -> Option<U> {
-    //     loop {
-    //         if !yield_val.is_empty() {
-    //             let rv = yield_val.pop_front();
-    //             if let Some(v) = rv {
-    //                 return Some(v);
-    //             }
-    //         }
-    //         if !is_yield {
-    //             let r = yields(&mut s.data);
-    //             if let Some(mut v) = r {
-    //                 is_yield = true;
-    //                 let rv = v.pop_front();
-    //                 if !v.is_empty() {
-    //                     yield_val = v;
-    //                 }
-    //                 if let Some(v) = rv {
-    //                     return Some(v);
-    //                 }
-    //             }
-    //         }
-    //         is_yield = false;
-
-    //         if !s.queue.is_empty() {
-    //             let mut q = s.queue.pop().unwrap();
-    //             q(&mut s);
-    //             continue;
-    //         }
-
-    //         if finish {
-    //             break;
-    //         }
-
-    //         let c = next(&mut s.data);
-    //         if c.is_none() {
-    //             finish = true;
-    //             let r = call(&mut s, Default::default(), false);
-    //             if r.is_none() {
-    //                 break;
-    //             }
-    //             continue;
-    //         }
-    //         let c: RecunsResult<I> = c.unwrap();
-    //         let c = match c {
-    //             Ok(c) => c,
-    //             Err(err) => {
-    //                 s.errors.push(err);
-    //                 return None;
-    //             }
-    //         };
-
-    //         let r = call(&mut s, c, false);
-    //         if r.is_none() {
-    //             break;
-    //         }
-    //     }
-    //     None
-    // });
-    // i
-}
-
-struct DoLoopIter<F> {
-    f: F,
-}
-impl<F, U> DoLoopIter<F>
-where
-    F: FnMut() -> Option<U>,
-{
-    #[inline]
-    pub fn new(f: F) -> Self {
-        Self { f }
-    }
+    Driver::new(data, root)
+        .stop_when_err(stop_when_err)
+        .iter(errors, warnings, next, yields)
 }
-impl<F, U> Iterator for DoLoopIter<F>
-where
-    F: FnMut() -> Option<U>,
-{
-    type Item = U;
 
-    #[inline]
-    fn next(&mut self) -> Option<Self::Item> {
-        (self.f)()
-    }
-}
-impl<F> Debug for DoLoopIter<F> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        f.debug_struct("DoLoopIter").field("f", &"...").finish()
-    }
+#[cfg(feature = "async")]
+#[inline]
+pub fn do_iter_async<'a, I: 'a, D: 'a, E: From<RecunsError> + 'a, U: 'a>(
+    data: D,
+    root: impl Recuns<E, Data = D, Input = I> + 'a,
+    stop_when_err: bool,
+    errors: &'a mut Vec<Diagnostic<E>>,
+    warnings: &'a mut Vec<Diagnostic<E>>,
+    next: impl 'a + Stream<Item = RecunsResult<I, E>> + Unpin,
+    yields: impl 'a + FnMut(&mut D) -> Option<VecDeque<U>>,
+) -> impl 'a + Stream<Item = U> {
+    Driver::new(data, root)
+        .stop_when_err(stop_when_err)
+        .iter_async(errors, warnings, next, yields)
 }
 
 #[macro_export]
@@ -3,13 +3,13 @@ use crate::*;
 use anyhow::Error;
 use batch_oper::*;
 use lazy_static::*;
+use std::any::Any;
 use std::collections::BTreeMap;
 use std::ops::*;
 use thiserror::*;
 use token::*;
 
-static CODE: &'static str =
-    r#"{ "a": 1, "b": true, "c": [null, 1.5, false], "d": { "v": "asd" } }"#;
+static CODE: &str = r#"{ "a": 1, "b": true, "c": [null, 1.5, false], "d": { "v": "asd" } }"#;
 
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub enum JsonValue {
@@ -29,6 +29,8 @@ pub enum JsonParserError {
     ExcessToken(Token),
     #[error("Need <{}> but find <{}>", .0, .1)]
     NeedBut(String, Token),
+    #[error("Duplicate key \"{}\"", .0)]
+    DuplicateKey(String),
 }
 
 #[derive(Debug)]
@@ -38,6 +40,11 @@ struct ParserData {
 
 type Flow = RecunsFlow<Token, ParserData>;
 
+/// Report `e` over the chars of the token it is about
+fn token_err(e: JsonParserError, inp: &Token) -> Flow {
+    Flow::diag(Diagnostic::error(Arc::new(Error::new(e))).span(inp.span()))
+}
+
 #[test]
 fn test_json() {
     let r = json(CODE.chars());
@@ -70,6 +77,253 @@ fn test_json() {
     )
 }
 
+#[test]
+fn test_json_nested() {
+    let r = json(r#"[[1, [true]], {"a": [null]}]"#.chars()).unwrap();
+    assert_eq!(
+        r,
+        Some(JsonValue::Arr(vec![
+            JsonValue::Arr(vec![
+                JsonValue::Num(1.0),
+                JsonValue::Arr(vec![JsonValue::Bool(true)]),
+            ]),
+            JsonValue::Obj({
+                let mut m = BTreeMap::new();
+                m.insert("a".into(), JsonValue::Arr(vec![JsonValue::Null]));
+                m
+            }),
+        ]))
+    )
+}
+
+#[test]
+fn test_json_feed() {
+    let toks = tokens(r#"{"a": [1, 2]}"#.chars()).unwrap();
+    let mut p = Parser::new(ParserData { out: None }, Root, true);
+    let (a, b) = toks.split_at(3);
+    assert!(p.feed_all(a.iter().cloned()));
+    assert_eq!(p.data().out, None);
+    assert!(p.feed_all(b.iter().cloned()));
+    let r = p.finish().result.unwrap();
+    assert_eq!(
+        r.out,
+        Some(JsonValue::Obj({
+            let mut m = BTreeMap::new();
+            m.insert(
+                "a".into(),
+                JsonValue::Arr(vec![JsonValue::Num(1.0), JsonValue::Num(2.0)]),
+            );
+            m
+        }))
+    );
+
+    let mut p = Parser::new(ParserData { out: None }, Root, true);
+    let toks = tokens("[1 2]".chars()).unwrap();
+    assert!(!p.feed_all(toks));
+    assert!(p.is_finished());
+    assert!(p.finish().result.is_err());
+}
+
+#[test]
+fn test_json_stack_names() {
+    let toks = tokens(r#"{"a": [[1]]}"#.chars()).unwrap();
+    let mut toks = toks.into_iter();
+    let mut deepest = vec![];
+    do_loop_on_loop(
+        ParserData { out: None },
+        Root,
+        true,
+        |_| toks.next().map(Ok),
+        |s| {
+            if s.depth() > deepest.len() {
+                assert_eq!(s.top_name(), s.stack_names().last().copied());
+                deepest = s.stack_names().to_vec();
+            }
+        },
+    )
+    .result
+    .unwrap();
+    assert_eq!(
+        deepest.join(" > "),
+        "root > check_obj > check_arr > check_arr"
+    );
+}
+
+#[test]
+fn test_json_error_context() {
+    let e = json(r#"{"a": [1 2]}"#.chars()).unwrap_err();
+    assert_eq!(e.len(), 1);
+    assert_eq!(e[0].path(), "root > check_obj > check_arr");
+    assert_eq!(e[0].index, 5);
+    assert!(!e[0].eof);
+    assert!(matches!(
+        e[0].downcast_ref::<JsonParserError>(),
+        Some(JsonParserError::NeedBut(_, Token::Num(_, _)))
+    ));
+
+    let e = json("[1, :]".chars()).unwrap_err();
+    assert_eq!(e[0].code, Some("R0009"));
+    assert_eq!((e[0].index, e[0].path()), (3, "root > check_arr".into()));
+    assert_eq!(
+        e[0].error.to_string(),
+        "Expected one of `[`, `{`, string, number, `true`, `false`, `null`, `]`"
+    );
+
+    let code = "[1,   :]";
+    let e = json(code.chars()).unwrap_err();
+    assert_eq!(e[0].span, Some(6..7));
+    assert_eq!(
+        e[0].render(code),
+        "error[R0009]: Expected one of `[`, `{`, string, number, `true`, `false`, `null`, `]`\n --> 1:7\n  |\n1 | [1,   :]\n  |       ^\n"
+    );
+
+    let e = json("[1,".chars()).unwrap_err();
+    assert_eq!(e[0].stack, vec!["root", "check_arr"]);
+    assert_eq!(e[0].index, 3);
+    assert!(e[0].eof);
+    assert_eq!(
+        e[0].to_string(),
+        "Need <]> but find EOF (at EOF in root > check_arr)"
+    );
+}
+
+#[test]
+fn test_json_render() {
+    let code = "{\"a\": [1,\n  2 3]}";
+    let e = json(code.chars()).unwrap_err();
+    assert_eq!(e[0].span, Some(14..15));
+    assert_eq!(
+        Snippet::new(code).name("a.json").render(&e[0]),
+        "error: Need <,> but find <Num(3) at 15..15>\n --> a.json:2:5\n  |\n2 |   2 3]}\n  |     ^\n"
+    );
+}
+
+#[test]
+fn test_json_recover() {
+    let code = r#"{"a": [1 2, 3], "b" 4, "c": [5}"#;
+    let e = json_recover(code);
+    let found = e
+        .iter()
+        .map(|e| match e.downcast_ref::<JsonParserError>() {
+            Some(JsonParserError::NeedBut(need, found)) => (need.as_str(), found.span()),
+            e => panic!("{:?}", e),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(found, vec![(",", 9..10), (":", 20..21), (",", 30..31)]);
+
+    let e = json_recover(r#"[1, {"a": "#);
+    assert_eq!(e.len(), 2);
+    assert_eq!(e[0].stack, vec!["root", "check_arr", "check_obj"]);
+    assert_eq!(e[1].stack, vec!["root", "check_arr"]);
+    assert!(e.iter().all(|e| e.eof));
+    assert_eq!(
+        e[1].downcast_ref::<JsonParserError>(),
+        Some(&JsonParserError::NeedButEof("]".into()))
+    );
+
+    // A frame that failed before the end still gets `on_eof`
+    let e = json_recover("[1 2");
+    assert_eq!(e.len(), 2);
+    assert!(!e[0].eof);
+    assert_eq!(
+        e[1].downcast_ref::<JsonParserError>(),
+        Some(&JsonParserError::NeedButEof("]".into()))
+    );
+
+    let mut p = Parser::new(ParserData { out: None }, Root, true).recover();
+    assert!(p.feed_all(tokens("[1 2, [3 4], 5]".chars()).unwrap()));
+    assert_eq!(p.finish().result.unwrap_err().len(), 2);
+
+    // A frame that syncs on an input it errors on skips it instead of erroring again
+    struct Semi;
+    impl Recuns for Semi {
+        type Input = char;
+        type Data = ();
+
+        fn check(&mut self, &inp: &char, _: &mut ()) -> RecunsFlow<char, ()> {
+            if inp == ';' {
+                return anyhow::anyhow!("unexpected ;").into();
+            }
+            RecunsFlow::None
+        }
+
+        fn on_eof(&mut self, _: &mut ()) -> RecunsFlow<char, ()> {
+            RecunsFlow::End
+        }
+
+        fn sync(&mut self, &inp: &char, _: &mut ()) -> bool {
+            inp == ';'
+        }
+    }
+    for max in [Some(DEFAULT_MAX_REDO), None] {
+        let mut code = "a;b;".chars();
+        let e = Driver::new((), Semi)
+            .recover()
+            .max_redo(max)
+            .run(|_| code.next().map(Ok))
+            .result
+            .unwrap_err();
+        assert_eq!(e.iter().map(|e| e.index).collect::<Vec<_>>(), vec![1, 3]);
+    }
+}
+
+/// Every error in `code`, recovering after each
+fn json_recover(code: &str) -> Vec<Diagnostic> {
+    let mut tokens = tokens(code.chars()).unwrap().into_iter();
+    Driver::new(ParserData { out: None }, Root)
+        .recover()
+        .run(|_| tokens.next().map(Ok))
+        .result
+        .unwrap_err()
+}
+
+#[test]
+fn test_json_warnings() {
+    let code = r#"{"a": 1, "b": 2, "a": 3}"#;
+    let mut tokens = tokens(code.chars()).unwrap().into_iter();
+    let Outcome { result, warnings } =
+        Driver::new(ParserData { out: None }, Root).run(|_| tokens.next().map(Ok));
+    let r = result.unwrap().unwrap().out;
+    assert_eq!(r, json(r#"{"a": 3, "b": 2}"#.chars()).unwrap());
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].severity, Severity::Warning);
+    assert_eq!(warnings[0].path(), "root > check_obj");
+    assert_eq!(
+        warnings[0].render(code),
+        "warning: Duplicate key \"a\"\n --> 1:18\n  |\n1 | {\"a\": 1, \"b\": 2, \"a\": 3}\n  |                  ^^^\n"
+    );
+}
+
+#[test]
+fn test_json_max_depth() {
+    let code = "[".repeat(100_000);
+    let mut p = Parser::new(ParserData { out: None }, Root, true).max_depth(64);
+    assert!(!p.feed_all(tokens(code.chars()).unwrap()));
+    assert_eq!(p.state().depth(), 64);
+    let e = p.finish().result.unwrap_err();
+    assert_eq!(
+        e[0].downcast_ref::<RecunsError>(),
+        Some(&RecunsError::MaxDepth(64))
+    );
+    assert_eq!(e[0].index, 63);
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn test_json_async() {
+    use futures::executor::block_on;
+    use futures::stream;
+
+    let toks = tokens(CODE.chars()).unwrap();
+    let r = block_on(do_loop_async(
+        ParserData { out: None },
+        Root,
+        true,
+        stream::iter(toks.into_iter().map(Ok)),
+    ));
+    assert_eq!(r.result.unwrap().unwrap().out, json(CODE.chars()).unwrap());
+}
+
 #[test]
 fn test_bench() {
     use easybench::bench;
@@ -84,161 +338,209 @@ fn test_bench() {
     }));
 }
 
-pub fn json(code: impl Iterator<Item = char>) -> Result<Option<JsonValue>, Vec<Arc<Error>>> {
+pub fn json(code: impl Iterator<Item = char>) -> RecunsResultErrs<Option<JsonValue>> {
     let tokens = tokens(code)?;
-    let mut tokens = tokens.iter();
-    let r = root.recuns();
-    let r = do_loop(ParserData { out: None }, r, true, |_| {
-        tokens.next().map(|v| Ok(v.clone()))
-    })?;
+    let mut tokens = tokens.into_iter();
+    let r = do_loop(ParserData { out: None }, Root, true, |_| {
+        tokens.next().map(Ok)
+    })
+    .result?;
     Ok(r.unwrap().out)
 }
 
-fn root(inp: Token, data: &mut ParserData, eof: bool) -> Flow {
-    if eof {
-        return Flow::End;
+#[inline]
+fn ret_value(v: Box<dyn Any>) -> JsonValue {
+    *v.downcast::<JsonValue>()
+        .expect("child frames only return JsonValue")
+}
+
+struct Root;
+impl Recuns for Root {
+    type Input = Token;
+    type Data = ParserData;
+
+    fn check(&mut self, inp: &Token, data: &mut ParserData) -> Flow {
+        if data.out.is_some() {
+            return token_err(JsonParserError::ExcessToken(inp.clone()), inp);
+        }
+        check_value(inp, &[], |v| data.out = Some(v))
+    }
+
+    fn on_ret(&mut self, value: Box<dyn Any>, data: &mut ParserData) -> Flow {
+        data.out = Some(ret_value(value));
+        Flow::None
     }
-    if data.out.is_some() {
-        return Error::new(JsonParserError::ExcessToken(inp)).into();
+
+    fn on_eof(&mut self, _: &mut ParserData) -> Flow {
+        Flow::End
     }
-    let data: *mut ParserData = data;
-    check_value(&inp, Box::new(move |v| unsafe { &mut *data }.out = Some(v)))
 }
-fn check_value(inp: &Token, mut cb: Box<dyn FnMut(JsonValue)>) -> Flow {
-    try_ret!(check_literal(inp, |v| cb(v)));
-    if let Some(f) = check_arr(inp) {
-        let r = f(Box::new(move |v| cb(v)));
-        return r;
+/// What can start a value
+static VALUE: &[&str] = &[
+    "`[`", "`{`", "string", "number", "`true`", "`false`", "`null`",
+];
+
+/// `also` is what else the frame would accept instead of a value
+fn check_value(inp: &Token, also: &[&'static str], cb: impl FnOnce(JsonValue)) -> Flow {
+    if let Some(v) = check_literal(inp) {
+        cb(v);
+        return Flow::None;
     }
-    if let Some(f) = check_obj(inp) {
-        let r = f(Box::new(move |v| cb(v)));
-        return r;
+    match inp {
+        Token::ArrS(_) => CheckArr::default().rfcall_next("check_arr"),
+        Token::ObjS(_) => CheckObj::default().rfcall_next("check_obj"),
+        _ => Flow::expect(VALUE.iter().chain(also).copied()).span(inp.span()),
     }
-    return Error::new(JsonParserError::NeedBut("value".into(), inp.clone())).into();
 }
-fn check_literal(inp: &Token, mut cb: impl FnMut(JsonValue)) -> Option<Flow> {
-    match inp {
-        Token::Str(s, _) => cb(JsonValue::Str(s.clone())),
-        &Token::Num(n, _) => cb(JsonValue::Num(n)),
-        &Token::Bool(b, _) => cb(JsonValue::Bool(b)),
-        Token::Null(_) => cb(JsonValue::Null),
+fn check_literal(inp: &Token) -> Option<JsonValue> {
+    Some(match inp {
+        Token::Str(s, _) => JsonValue::Str(s.clone()),
+        &Token::Num(n, _) => JsonValue::Num(n),
+        &Token::Bool(b, _) => JsonValue::Bool(b),
+        Token::Null(_) => JsonValue::Null,
         _ => return None,
+    })
+}
+
+#[derive(Default)]
+struct CheckArr {
+    vals: Vec<JsonValue>,
+    split: bool,
+}
+impl CheckArr {
+    #[inline]
+    fn push(&mut self, v: JsonValue) {
+        self.vals.push(v);
+        self.split = true;
     }
-    Some(Flow::None)
 }
-#[inline]
-fn check_arr(inp: &Token) -> Option<impl FnOnce(Box<dyn FnMut(JsonValue)>) -> Flow> {
-    if let Token::ArrS(_) = inp {
-        return Some(|mut cb: Box<dyn FnMut(JsonValue)>| {
-            let mut vals = vec![];
-            let mut split = false;
-            return move |inp: Token, _: &mut ParserData, eof: bool| -> Flow {
-                if eof {
-                    return Error::new(JsonParserError::NeedButEof("]".into())).into();
-                }
-                if let Token::ArrE(_) = inp {
-                    cb(JsonValue::Arr(std::mem::replace(&mut vals, vec![])));
-                    return Flow::End;
-                }
-                if split {
-                    split = false;
-                    if let Token::Comma(_) = inp {
-                        return Flow::None;
-                    } else {
-                        return Error::new(JsonParserError::NeedBut(",".into(), inp)).into();
-                    }
-                } else {
-                    let vals: *mut Vec<JsonValue> = &mut vals;
-                    let split: *mut bool = &mut split;
-                    check_value(
-                        &inp,
-                        Box::new(move |v| unsafe {
-                            (&mut *vals).push(v);
-                            *split = true;
-                        }),
-                    );
-                }
+impl Recuns for CheckArr {
+    type Input = Token;
+    type Data = ParserData;
 
+    fn check(&mut self, inp: &Token, _: &mut ParserData) -> Flow {
+        if let Token::ArrE(_) = inp {
+            return Flow::ret(JsonValue::Arr(std::mem::take(&mut self.vals)));
+        }
+        if self.split {
+            self.split = false;
+            if let Token::Comma(_) = inp {
                 Flow::None
+            } else {
+                token_err(JsonParserError::NeedBut(",".into(), inp.clone()), inp)
             }
-            .rfcall_next("check_arr");
-        });
+        } else {
+            check_value(inp, &["`]`"], |v| self.push(v))
+        }
     }
-    None
-}
-#[inline]
-fn check_obj(inp: &Token) -> Option<impl FnOnce(Box<dyn FnMut(JsonValue)>) -> Flow> {
-    if let Token::ObjS(_) = inp {
-        return Some(|mut cb: Box<dyn FnMut(JsonValue)>| {
-            enum Need {
-                Key,
-                Colon,
-                Value,
-                Comma,
+
+    fn on_eof(&mut self, _: &mut ParserData) -> Flow {
+        Error::new(JsonParserError::NeedButEof("]".into())).into()
+    }
+
+    fn sync(&mut self, inp: &Token, _: &mut ParserData) -> bool {
+        match inp {
+            Token::Comma(_) => {
+                self.split = true;
+                true
             }
-            let mut vals = BTreeMap::new();
-            let mut key = None;
-            let mut need = Need::Key;
-            return move |inp: Token, _: &mut ParserData, eof: bool| -> Flow {
-                if eof {
-                    return match need {
-                        Need::Colon => Error::new(JsonParserError::NeedButEof(":".into())).into(),
-                        Need::Value => {
-                            Error::new(JsonParserError::NeedButEof("value".into())).into()
-                        }
-                        _ => Error::new(JsonParserError::NeedButEof("]".into())).into(),
-                    };
+            Token::ArrE(_) => true,
+            _ => false,
+        }
+    }
+
+    fn on_ret(&mut self, value: Box<dyn Any>, _: &mut ParserData) -> Flow {
+        self.push(ret_value(value));
+        Flow::None
+    }
+}
+
+#[derive(Default)]
+enum Need {
+    #[default]
+    Key,
+    Colon,
+    Value,
+    Comma,
+}
+#[derive(Default)]
+struct CheckObj {
+    vals: BTreeMap<String, JsonValue>,
+    key: Option<String>,
+    need: Need,
+}
+impl CheckObj {
+    #[inline]
+    fn insert(&mut self, v: JsonValue) {
+        let k = self.key.take().unwrap();
+        self.vals.insert(k, v);
+        self.need = Need::Comma;
+    }
+}
+impl Recuns for CheckObj {
+    type Input = Token;
+    type Data = ParserData;
+
+    fn check(&mut self, inp: &Token, _: &mut ParserData) -> Flow {
+        if let Token::ObjE(_) = inp {
+            return Flow::ret(JsonValue::Obj(std::mem::take(&mut self.vals)));
+        }
+        match self.need {
+            Need::Key => {
+                if let Token::Str(k, _) = inp {
+                    self.key = Some(k.clone());
+                    self.need = Need::Colon;
+                    if self.vals.contains_key(k) {
+                        let e = Error::new(JsonParserError::DuplicateKey(k.clone()));
+                        return Flow::warn(Diagnostic::warning(Arc::new(e)).span(inp.span()));
+                    }
+                } else {
+                    return token_err(JsonParserError::NeedBut("key".into(), inp.clone()), inp);
                 }
-                if let Token::ObjE(_) = inp {
-                    cb(JsonValue::Obj(std::mem::replace(
-                        &mut vals,
-                        BTreeMap::new(),
-                    )));
-                    return Flow::End;
+            }
+            Need::Colon => {
+                if let Token::Colon(_) = inp {
+                    self.need = Need::Value;
+                } else {
+                    return token_err(JsonParserError::NeedBut(":".into(), inp.clone()), inp);
                 }
-                match need {
-                    Need::Key => {
-                        if let Token::Str(k, _) = inp {
-                            key = Some(k);
-                            need = Need::Colon;
-                        } else {
-                            return Error::new(JsonParserError::NeedBut("key".into(), inp)).into();
-                        }
-                    }
-                    Need::Colon => {
-                        if let Token::Colon(_) = inp {
-                            need = Need::Value;
-                        } else {
-                            return Error::new(JsonParserError::NeedBut(":".into(), inp)).into();
-                        }
-                    }
-                    Need::Value => {
-                        let vals: *mut _ = &mut vals;
-                        let key: *mut _ = &mut key;
-                        let need: *mut _ = &mut need;
-                        return check_value(
-                            &inp,
-                            Box::new(move |v| unsafe {
-                                let k = std::mem::replace(&mut *key, None).unwrap();
-                                (&mut *vals).insert(k, v);
-                                *need = Need::Comma;
-                            }),
-                        );
-                    }
-                    Need::Comma => {
-                        if let Token::Comma(_) = inp {
-                            need = Need::Key;
-                        } else {
-                            return Error::new(JsonParserError::NeedBut(",".into(), inp)).into();
-                        }
-                    }
+            }
+            Need::Value => return check_value(inp, &[], |v| self.insert(v)),
+            Need::Comma => {
+                if let Token::Comma(_) = inp {
+                    self.need = Need::Key;
+                } else {
+                    return token_err(JsonParserError::NeedBut(",".into(), inp.clone()), inp);
                 }
-                Flow::None
             }
-            .rfcall_next("check_obj");
-        });
+        }
+        Flow::None
+    }
+
+    fn on_eof(&mut self, _: &mut ParserData) -> Flow {
+        match self.need {
+            Need::Colon => Error::new(JsonParserError::NeedButEof(":".into())).into(),
+            Need::Value => Error::new(JsonParserError::NeedButEof("value".into())).into(),
+            _ => Error::new(JsonParserError::NeedButEof("}".into())).into(),
+        }
+    }
+
+    fn sync(&mut self, inp: &Token, _: &mut ParserData) -> bool {
+        match inp {
+            Token::Comma(_) => {
+                self.key = None;
+                self.need = Need::Comma;
+                true
+            }
+            Token::ObjE(_) => true,
+            _ => false,
+        }
+    }
+
+    fn on_ret(&mut self, value: Box<dyn Any>, _: &mut ParserData) -> Flow {
+        self.insert(ret_value(value));
+        Flow::None
     }
-    None
 }
 
 mod token {
@@ -246,12 +548,9 @@ mod token {
     use super::*;
     use anyhow::Error;
     use regex::*;
-    use std::collections::VecDeque;
-    use std::iter::FromIterator;
 
     #[derive(Debug, Clone, PartialEq)]
     pub enum Token {
-        None,
         Str(String, Range<usize>),
         Num(f64, Range<usize>),
         Bool(bool, Range<usize>),
@@ -269,15 +568,20 @@ mod token {
         /// `:`
         Colon(Range<usize>),
     }
-    impl Default for Token {
-        fn default() -> Self {
-            Self::None
+    impl Token {
+        /// The chars of the token counted from 0, its range counts them from 1 and includes the end
+        pub fn span(&self) -> Range<usize> {
+            let r = match self {
+                Token::Str(_, r) | Token::Num(_, r) | Token::Bool(_, r) => r,
+                Token::Null(r) | Token::ArrS(r) | Token::ArrE(r) | Token::ObjS(r) => r,
+                Token::ObjE(r) | Token::Comma(r) | Token::Colon(r) => r,
+            };
+            r.start - 1..r.end
         }
     }
     impl std::fmt::Display for Token {
         fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
             match self {
-                Token::None => write!(f, "None"),
                 Token::Str(s, r) => write!(f, "Str({}) at {}..{}", s, r.start, r.end),
                 Token::Num(n, r) => write!(f, "Num({}) at {}..{}", n, r.start, r.end),
                 Token::Bool(b, r) => write!(f, "Bool({}) at {}..{}", b, r.start, r.end),
@@ -294,28 +598,52 @@ mod token {
 
     #[derive(Error, Debug, PartialEq, Eq)]
     pub enum TokenError {
-        #[error("Token is not a legal number at {}..{}", .0.start, .0.end)]
-        NotNum(Range<usize>),
-        #[error("Need '{}' but find EOF at {}..{}", .0, .1, .1)]
-        NeedButEof(char, usize),
-        #[error("Need <{}> but find EOF at {}..{}", .0, .1, .1)]
-        NeedSomeButEof(String, usize),
-        #[error("Unexpected EOF at {}..{}", .0, .0)]
-        Eof(usize),
-        #[error("Special characters need to be escaped at {}..{}", .0, .0)]
-        NeedEscape(usize),
-        #[error("Illegal Escape symbol '{}' at {}..{}", .0, .1, .1)]
-        IllegalEscape(char, usize),
-        #[error("Unknown word \"{}\" at {}..{}", .0, .1.start, .1.end)]
-        UnknownWord(String, Range<usize>),
-        #[error( "Unknown character '{}' at {}..{}", .0, .1, .1)]
-        UnknownCharacter(char, usize),
+        #[error("Token is not a legal number")]
+        NotNum,
+        #[error("Need '{}' but find EOF", .0)]
+        NeedButEof(char),
+        #[error("Need <{}> but find EOF", .0)]
+        NeedSomeButEof(String),
+        #[error("Unexpected EOF")]
+        Eof,
+        #[error("Special characters need to be escaped")]
+        NeedEscape,
+        #[error("Illegal Escape symbol '{}'", .0)]
+        IllegalEscape(char),
+        #[error("Unknown word \"{}\"", .0)]
+        UnknownWord(String),
+        #[error("Unknown character '{}'", .0)]
+        UnknownCharacter(char),
+    }
+    impl TokenError {
+        fn code(&self) -> &'static str {
+            match self {
+                Self::NotNum => "T0001",
+                Self::NeedButEof(_) => "T0002",
+                Self::NeedSomeButEof(_) => "T0003",
+                Self::Eof => "T0004",
+                Self::NeedEscape => "T0005",
+                Self::IllegalEscape(_) => "T0006",
+                Self::UnknownWord(_) => "T0007",
+                Self::UnknownCharacter(_) => "T0008",
+            }
+        }
+    }
+    /// Report `e` over the chars `from..=to`, counted from 1 like `TokenData::index`
+    fn token_diag(e: TokenError, from: usize, to: usize) -> Diagnostic {
+        let code = e.code();
+        Diagnostic::error(Arc::new(Error::new(e)))
+            .code(code)
+            .span(from - 1..to)
+    }
+    /// Report `e` at the end of input, after the `len` chars read
+    fn eof_diag(e: TokenError, len: usize) -> Diagnostic {
+        token_diag(e, len + 1, len)
     }
 
     #[derive(Debug)]
     struct TokenData {
         index: usize,
-        tokens: Vec<Token>,
     }
     impl TokenData {
         #[inline]
@@ -364,51 +692,54 @@ mod token {
         );
     }
 
-    pub fn tokens(mut code: impl Iterator<Item = char>) -> Result<Vec<Token>, Vec<Arc<Error>>> {
-        let mut errors = vec![];
-        let r = root.recuns();
-        let r = do_iter(
-            TokenData {
-                index: 0,
-                tokens: vec![],
-            },
-            r,
-            true,
-            &mut errors,
-            |d| {
+    #[test]
+    fn test_token_diagnostics() {
+        let e = tokens("[tru]".chars()).unwrap_err();
+        assert_eq!(e[0].code, Some("T0007"));
+        assert_eq!(e[0].span, Some(1..4));
+        assert_eq!(e[0].severity, Severity::Error);
+        assert_eq!(
+            e[0].downcast_ref::<TokenError>(),
+            Some(&TokenError::UnknownWord("tru".into()))
+        );
+
+        let e = tokens(r#"["ab"#.chars()).unwrap_err();
+        assert_eq!(e[0].code, Some("T0002"));
+        assert!(e[0].eof);
+        assert_eq!(e[0].primary_span(), 4..4);
+        assert_eq!(
+            e[0].labels,
+            vec![Label {
+                span: 1..2,
+                message: "string starts here".into()
+            }]
+        );
+    }
+
+    pub fn tokens(mut code: impl Iterator<Item = char>) -> RecunsResultErrs<Vec<Token>> {
+        Driver::new(TokenData { index: 0 }, root.recuns())
+            .try_iter_yielded(|d| {
                 code.next().map(|v| {
                     d.index += 1;
                     Ok(v)
                 })
-            },
-            |d| {
-                if d.tokens.is_empty() {
-                    return None;
-                }
-                let y = VecDeque::from_iter(d.tokens.iter().cloned());
-                d.tokens = vec![];
-                Some(y)
-            },
-        );
-        let r = r.collect::<Vec<_>>();
-        if errors.is_empty() {
-            Ok(r)
-        } else {
-            Err(errors)
-        }
+            })
+            .collect::<Result<_, _>>()
+            .map_err(|e| vec![e])
     }
 
-    fn root(inp: char, data: &mut TokenData, eof: bool) -> Flow {
-        if eof || inp == '\0' {
-            return Flow::End;
-        }
+    fn root(inp: Option<&char>, data: &mut TokenData) -> Flow {
+        let inp = match inp {
+            Some(&c) => c,
+            None => return Flow::End,
+        };
         let sp = data.save();
         try_ret!(check_number(inp, sp));
         try_ret!(check_string(inp, sp));
         try_ret!(check_word(inp, sp));
         try_ret!(check_space(inp, sp));
         try_ret!(check_symbol(inp, data, sp));
-        return Error::new(TokenError::UnknownCharacter(inp, sp)).into();
+        Flow::diag(token_diag(TokenError::UnknownCharacter(inp), sp, sp))
     }
 
     lazy_static! {
@@ -426,28 +757,26 @@ mod token {
         }
         if is_num_start(first) {
             let mut strs = vec![first];
-            return move |inp, data: &mut TokenData, eof| -> Flow {
-                if eof || inp == '\0' || !is_num(inp) {
-                    let s = strs.iter().collect::<String>();
-                    let np = data.save() - 1;
-                    if !number_regex.is_match(&*s) {
-                        return Error::new(TokenError::NotNum(sp..np)).into();
+            return (move |inp: Option<&char>, data: &mut TokenData| -> Flow {
+                match inp {
+                    Some(&c) if is_num(c) => {
+                        strs.push(c);
+                        Flow::None
                     }
-                    let f = s.parse::<f64>();
-                    match f {
-                        Ok(f) => {
-                            data.tokens.push(Token::Num(f, sp..np));
-                            return Flow::EndReDo;
+                    _ => {
+                        let s = strs.iter().collect::<String>();
+                        let np = data.save() - 1;
+                        if !number_regex.is_match(&s) {
+                            return Flow::diag(token_diag(TokenError::NotNum, sp, np));
                         }
-                        Err(_) => {
-                            return Error::new(TokenError::NotNum(sp..np)).into();
+                        let f = s.parse::<f64>();
+                        match f {
+                            Ok(f) => Flow::emit_then(Token::Num(f, sp..np), Flow::EndReDo),
+                            Err(_) => Flow::diag(token_diag(TokenError::NotNum, sp, np)),
                         }
                     }
-                } else {
-                    strs.push(inp);
-                    return Flow::None;
                 }
-            }
+            })
             .rfcall_next("check_number")
             .into();
         }
@@ -455,38 +784,59 @@ mod token {
     }
     fn check_string(first: char, sp: usize) -> Option<Flow> {
         if first == '"' {
-            let mut strs = vec![];
-            return move |inp, data: &mut TokenData, eof| -> Flow {
-                if eof || inp == '\0' {
-                    let np = data.save();
-                    return Error::new(TokenError::NeedButEof('"', np)).into();
-                }
-                //                  \b   \f
-                if bop!(|| inp; ==; '', '', '\n', '\r', '\t') {
-                    let np = data.save();
-                    return Error::new(TokenError::NeedEscape(np)).into();
-                }
-                if inp == '"' {
-                    let s: String = strs.iter().collect();
-                    let np = data.save();
-                    data.tokens.push(Token::Str(s, sp..np));
-                    return Flow::End;
-                }
-                try_ret!(check_escape(inp, data.save(), &mut strs));
-                strs.push(inp);
-                Flow::None
-            }
-            .rfcall_next("check_string")
-            .into();
+            return CheckString { sp, strs: vec![] }
+                .rfcall_next("check_string")
+                .into();
         }
         None
     }
-    fn check_escape(first: char, _: usize, strs: *mut Vec<char>) -> Option<Flow> {
+    struct CheckString {
+        sp: usize,
+        strs: Vec<char>,
+    }
+    impl Recuns for CheckString {
+        type Input = char;
+        type Data = TokenData;
+
+        fn check(&mut self, &inp: &char, data: &mut TokenData) -> Flow {
+            //                  \b   \f
+            if bop!(|| inp; ==; '\u{8}', '\u{c}', '\n', '\r', '\t') {
+                let np = data.save();
+                return Flow::diag(
+                    token_diag(TokenError::NeedEscape, np, np)
+                        .note("control characters are written as escapes like \\n in strings"),
+                );
+            }
+            if inp == '"' {
+                let s: String = self.strs.iter().collect();
+                let np = data.save();
+                return Flow::emit_then(Token::Str(s, self.sp..np), Flow::End);
+            }
+            try_ret!(check_escape(inp, data.save()));
+            self.strs.push(inp);
+            Flow::None
+        }
+
+        fn on_eof(&mut self, data: &mut TokenData) -> Flow {
+            let np = data.save();
+            Flow::diag(
+                eof_diag(TokenError::NeedButEof('"'), np)
+                    .label(self.sp - 1..self.sp, "string starts here"),
+            )
+        }
+
+        fn on_ret(&mut self, value: Box<dyn Any>, _: &mut TokenData) -> Flow {
+            self.strs
+                .push(*value.downcast::<char>().expect("escapes only return char"));
+            Flow::None
+        }
+    }
+    fn check_escape(first: char, _: usize) -> Option<Flow> {
         fn doesc(c: char) -> char {
             match c {
                 '\\' | '"' | '/' => c,
-                'b' => '',
-                'f' => '',
+                'b' => '\u{8}',
+                'f' => '\u{c}',
                 'n' => '\n',
                 'r' => '\r',
                 't' => '\t',
@@ -494,42 +844,45 @@ mod token {
             }
         }
         if first == '\\' {
-            return move |inp, data: &mut TokenData, eof| -> Flow {
-                if eof || inp == '\0' {
-                    let np = data.save();
-                    return Error::new(TokenError::NeedSomeButEof("Escape Character".into(), np))
-                        .into();
-                }
+            return (move |inp: Option<&char>, data: &mut TokenData| -> Flow {
+                let inp = match inp {
+                    Some(&c) => c,
+                    None => {
+                        let np = data.save();
+                        return Flow::diag(eof_diag(
+                            TokenError::NeedSomeButEof("Escape Character".into()),
+                            np,
+                        ));
+                    }
+                };
                 if bop!(|| inp; ==; '\\', '"', '/', 'b', 'f', 'n', 'r', 't') {
-                    unsafe { &mut *strs }.push(doesc(inp));
-                    return Flow::End;
+                    Flow::ret(doesc(inp))
                 } else if inp == 'u' {
                     let mut uc = vec![];
-                    return move |inp: char, data: &mut TokenData, eof| -> Flow {
-                        if eof || inp == '\0' {
-                            let np = data.save();
-                            return Error::new(TokenError::Eof(np)).into();
-                        }
+                    (move |inp: Option<&char>, data: &mut TokenData| -> Flow {
+                        let inp = match inp {
+                            Some(&c) => c,
+                            None => return Flow::diag(eof_diag(TokenError::Eof, data.save())),
+                        };
                         if !inp.is_ascii_hexdigit() {
                             let np = data.save();
-                            return Error::new(TokenError::IllegalEscape(inp, np)).into();
+                            return Flow::diag(token_diag(TokenError::IllegalEscape(inp), np, np));
                         }
                         uc.push(inp);
                         if uc.len() == 4 {
                             let s: String = uc.iter().collect();
-                            let hex: u32 = u32::from_str_radix(&*s, 16).unwrap();
+                            let hex: u32 = u32::from_str_radix(&s, 16).unwrap();
                             let c = std::char::from_u32(hex).unwrap();
-                            unsafe { &mut *strs }.push(c);
-                            return Flow::End;
+                            return Flow::ret(c);
                         }
                         Flow::None
-                    }
-                    .rfmov_next("check_escape_unicode".into());
+                    })
+                    .rfmov_next("check_escape_unicode")
                 } else {
                     let np = data.save();
-                    return Error::new(TokenError::IllegalEscape(inp, np)).into();
+                    Flow::diag(token_diag(TokenError::IllegalEscape(inp), np, np))
                 }
-            }
+            })
             .rfcall_next("check_escape")
             .into();
         }
@@ -538,24 +891,23 @@ mod token {
     fn check_word(first: char, sp: usize) -> Option<Flow> {
         if first.is_alphanumeric() {
             let mut ws = vec![first];
-            return move |inp: char, data: &mut TokenData, eof| -> Flow {
-                if eof || inp == '\0' || !inp.is_alphanumeric() {
-                    let s: String = ws.iter().collect();
-                    let np = data.save() - 1;
-                    if s == "true" {
-                        data.tokens.push(Token::Bool(true, sp..np));
-                    } else if s == "false" {
-                        data.tokens.push(Token::Bool(false, sp..np));
-                    } else if s == "null" {
-                        data.tokens.push(Token::Null(sp..np));
-                    } else {
-                        return Error::new(TokenError::UnknownWord(s, sp..np)).into();
-                    }
-                    return Flow::EndReDo;
+            return (move |inp: Option<&char>, data: &mut TokenData| -> Flow {
+                if let Some(c) = inp.copied().filter(|c| c.is_alphanumeric()) {
+                    ws.push(c);
+                    return Flow::None;
                 }
-                ws.push(inp);
-                Flow::None
-            }
+                let s: String = ws.iter().collect();
+                let np = data.save() - 1;
+                let token = match &*s {
+                    "true" => Token::Bool(true, sp..np),
+                    "false" => Token::Bool(false, sp..np),
+                    "null" => Token::Null(sp..np),
+                    _ => {
+                        return Flow::diag(token_diag(TokenError::UnknownWord(s), sp, np));
+                    }
+                };
+                Flow::emit_then(token, Flow::EndReDo)
+            })
             .rfcall_next("check_word")
             .into();
         }
@@ -564,31 +916,25 @@ mod token {
     #[inline]
     fn check_symbol(first: char, data: &mut TokenData, sp: usize) -> Option<Flow> {
         let np = data.save();
-        if first == ',' {
-            data.tokens.push(Token::Comma(sp..np))
-        } else if first == ':' {
-            data.tokens.push(Token::Colon(sp..np))
-        } else if first == '{' {
-            data.tokens.push(Token::ObjS(sp..np))
-        } else if first == '}' {
-            data.tokens.push(Token::ObjE(sp..np))
-        } else if first == '[' {
-            data.tokens.push(Token::ArrS(sp..np))
-        } else if first == ']' {
-            data.tokens.push(Token::ArrE(sp..np))
-        } else {
-            return None;
-        }
-        Some(Flow::None)
+        let token = match first {
+            ',' => Token::Comma(sp..np),
+            ':' => Token::Colon(sp..np),
+            '{' => Token::ObjS(sp..np),
+            '}' => Token::ObjE(sp..np),
+            '[' => Token::ArrS(sp..np),
+            ']' => Token::ArrE(sp..np),
+            _ => return None,
+        };
+        Some(Flow::emit(token))
     }
     fn check_space(first: char, _: usize) -> Option<Flow> {
         if first.is_whitespace() {
-            return move |inp: char, _: &mut TokenData, eof| -> Flow {
-                if eof || inp == '\0' || !inp.is_whitespace() {
-                    return Flow::EndReDo;
+            return (move |inp: Option<&char>, _: &mut TokenData| -> Flow {
+                match inp {
+                    Some(c) if c.is_whitespace() => Flow::None,
+                    _ => Flow::EndReDo,
                 }
-                Flow::None
-            }
+            })
             .rfcall_next("check_space")
             .into();
         }